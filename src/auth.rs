@@ -0,0 +1,768 @@
+use crate::config;
+use crate::credential_helper::{self, Credential};
+use crate::git_url;
+use crate::github_app::GithubAppArgs;
+use crate::netrc;
+use crate::redact;
+use crate::result::{Error, PipelineError, Result};
+use clap::{Args, ValueEnum};
+use git2::{Cred, CredentialType, ErrorClass, ErrorCode, RemoteCallbacks};
+use log::{info, trace, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Once, OnceLock};
+
+/// Keys that are tried when no `--ssh-key` is given, relative to `~/.ssh`
+const DEFAULT_SSH_KEYS: &[&str] = &["id_ed25519", "id_rsa"];
+
+static AZURE_PIPELINES_TOKEN_LOGGED: Once = Once::new();
+
+static GITLAB_JOB_TOKEN_LOGGED: Once = Once::new();
+
+static STDIN_PASSWORD: OnceLock<std::io::Result<String>> = OnceLock::new();
+
+#[derive(Args, Serialize, Debug, Clone)]
+pub struct AuthArgs {
+    /// Private SSH key used for SSH remotes [default: ~/.ssh/id_ed25519,
+    /// ~/.ssh/id_rsa]
+    #[arg(long, env = "DEPLOYMENT_SSH_KEY")]
+    ssh_key: Option<PathBuf>,
+
+    /// Public part of the SSH key, if it is not next to the private key
+    #[arg(long, requires = "ssh_key", env = "DEPLOYMENT_SSH_PUBLIC_KEY")]
+    ssh_public_key: Option<PathBuf>,
+
+    /// Passphrase of the private SSH key
+    #[arg(long, env = "DEPLOYMENT_SSH_PASSPHRASE", hide_env_values = true)]
+    #[serde(serialize_with = "config::secret")]
+    ssh_passphrase: Option<String>,
+
+    /// File containing the passphrase of the private SSH key, which also
+    /// unlocks --sign-with-ssh-key
+    #[arg(
+        long,
+        conflicts_with = "ssh_passphrase",
+        env = "DEPLOYMENT_SSH_KEY_PASSPHRASE_FILE"
+    )]
+    ssh_key_passphrase_file: Option<PathBuf>,
+
+    /// SSH key for the remotes on a host or below a URL prefix, as
+    /// `<host or URL prefix>=<key file>`. It is tried before the ssh-agent
+    /// and --ssh-key, the longest matching prefix wins. Several ones in the
+    /// environment variable are separated by newlines.
+    #[arg(
+        long,
+        value_name = "PREFIX=KEY",
+        value_parser = parse_deploy_key,
+        env = "DEPLOYMENT_DEPLOY_KEYS",
+        value_delimiter = '\n'
+    )]
+    #[serde(serialize_with = "config::pairs")]
+    deploy_key: Vec<(String, PathBuf)>,
+
+    /// Do not ask the ssh-agent for keys
+    #[arg(long, env = "DEPLOYMENT_NO_SSH_AGENT")]
+    no_ssh_agent: bool,
+
+    /// Token used to authenticate against HTTPS remotes on the host of the
+    /// composite repository or a --token-host. It is sent as bearer token,
+    /// or as basic auth for Azure DevOps remotes
+    #[arg(long, env = "DEPLOYMENT_TOKEN", hide_env_values = true)]
+    #[serde(serialize_with = "config::secret")]
+    token: Option<String>,
+
+    /// File containing the token for --token, so it does not show up in
+    /// process listings or the shell history
+    #[arg(long, conflicts_with = "token", env = "DEPLOYMENT_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+
+    /// How --token and --push-token are sent [default: bearer, basic for
+    /// Azure DevOps remotes]
+    #[arg(long, value_enum, env = "DEPLOYMENT_TOKEN_TYPE")]
+    token_type: Option<TokenType>,
+
+    /// Token used instead of --token when pushing, for example when the
+    /// composite repository is cloned from a read-only mirror
+    #[arg(long, env = "DEPLOYMENT_PUSH_TOKEN", hide_env_values = true)]
+    #[serde(serialize_with = "config::secret")]
+    push_token: Option<String>,
+
+    /// Another host that --token and --push-token are sent to, for example
+    /// one of submodules or of the child repository. Several ones in the
+    /// environment variable are separated by newlines.
+    #[arg(
+        long,
+        value_name = "HOST",
+        value_delimiter = '\n',
+        env = "DEPLOYMENT_TOKEN_HOSTS"
+    )]
+    token_host: Vec<String>,
+
+    /// Hosts of the composite repository, they are sent the tokens
+    #[arg(skip)]
+    #[serde(skip)]
+    composite_hosts: Vec<String>,
+
+    /// Username for HTTPS remotes
+    #[arg(long, requires = "password_file", env = "DEPLOYMENT_USERNAME")]
+    username: Option<String>,
+
+    /// File containing the password for --username, `-` reads it from stdin
+    #[arg(long, requires = "username", env = "DEPLOYMENT_PASSWORD_FILE")]
+    password_file: Option<PathBuf>,
+
+    /// Access token for Azure DevOps remotes [default: $SYSTEM_ACCESSTOKEN
+    /// when running in Azure Pipelines]
+    #[arg(long, env = "DEPLOYMENT_AZURE_DEVOPS_TOKEN", hide_env_values = true)]
+    #[serde(serialize_with = "config::secret")]
+    azure_devops_token: Option<String>,
+
+    /// CI job token for GitLab remotes, it is sent as the password of
+    /// `gitlab-ci-token` [default: $CI_JOB_TOKEN when running in GitLab CI]
+    #[arg(long, env = "DEPLOYMENT_GITLAB_JOB_TOKEN", hide_env_values = true)]
+    #[serde(serialize_with = "config::secret")]
+    gitlab_job_token: Option<String>,
+
+    /// Ask `git credential` for HTTPS credentials, so the configured
+    /// credential helpers are used
+    #[arg(long, env = "DEPLOYMENT_USE_CREDENTIAL_HELPER")]
+    use_credential_helper: bool,
+
+    /// Netrc file with HTTPS credentials [default: $NETRC or ~/.netrc]
+    #[arg(long, env = "DEPLOYMENT_NETRC_FILE")]
+    netrc_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    github_app: GithubAppArgs,
+}
+
+/// The authorization scheme of the header that carries a token
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    /// `AUTHORIZATION: Bearer <token>`
+    Bearer,
+    /// `AUTHORIZATION: basic <base64 of :token>`, a token of the form
+    /// `<user>:<token>` is encoded with its user
+    Basic,
+}
+
+fn parse_deploy_key(
+    value: &str,
+) -> std::result::Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((prefix, path)) if !prefix.is_empty() && !path.is_empty() => {
+            Ok((prefix.to_string(), PathBuf::from(path)))
+        }
+        _ => Err("expected <host or URL prefix>=<key file>".to_string()),
+    }
+}
+
+impl AuthArgs {
+    /// Reads `--token-file` into the token, once before anything is fetched
+    pub fn load_token_file(&mut self) -> Result<()> {
+        let Some(path) = &self.token_file else {
+            return Ok(());
+        };
+        let token = fs::read_to_string(path).map_err(|e| {
+            PipelineError::InvalidArguments(format!(
+                "Could not read token file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(PipelineError::InvalidArguments(format!(
+                "Token file {} is empty",
+                path.display()
+            )));
+        }
+        self.token = Some(token.to_string());
+        Ok(())
+    }
+
+    /// Sends --token and --push-token to the host of `url`, a URL of the
+    /// composite repository
+    pub fn allow_token_for(&mut self, url: &str) {
+        if let Some(host) = git_url::host(url) {
+            if !self.composite_hosts.iter().any(|x| x == host) {
+                self.composite_hosts.push(host.to_string());
+            }
+        }
+    }
+
+    /// Whether the tokens may be sent to `url`. Other hosts, like the ones
+    /// of submodules, must not see a token meant for the composite
+    /// repository.
+    fn sends_token_to(&self, url: &str) -> bool {
+        let Some(host) = git_url::host(url) else {
+            return false;
+        };
+        self.composite_hosts
+            .iter()
+            .chain(&self.token_host)
+            .any(|x| x.eq_ignore_ascii_case(host))
+    }
+
+    /// The `--deploy-key` for `url`. Prefixes have to end at a `/` or `:` of
+    /// the URL, so `https://github.com/org` does not match `org-other`.
+    fn deploy_key(&self, url: &str) -> Option<&Path> {
+        let host = git_url::host(url);
+        self.deploy_key
+            .iter()
+            .filter(|(prefix, _)| {
+                host == Some(prefix.as_str())
+                    || git_url::has_prefix(url, prefix)
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, path)| path.as_path())
+    }
+
+    fn use_ssh_agent(&self) -> bool {
+        !self.no_ssh_agent && std::env::var_os("SSH_AUTH_SOCK").is_some()
+    }
+
+    fn ssh_keys(&self) -> Vec<PathBuf> {
+        if let Some(ssh_key) = &self.ssh_key {
+            return vec![ssh_key.clone()];
+        }
+        let home = match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home),
+            None => return vec![],
+        };
+        DEFAULT_SSH_KEYS
+            .iter()
+            .map(|name| home.join(".ssh").join(name))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    /// The passphrase file of the SSH key, for signing with it
+    pub fn ssh_key_passphrase_file(&self) -> Option<&Path> {
+        self.ssh_key_passphrase_file.as_deref()
+    }
+
+    fn ssh_passphrase(
+        &self,
+    ) -> std::result::Result<Option<String>, git2::Error> {
+        if let Some(path) = &self.ssh_key_passphrase_file {
+            let passphrase = fs::read_to_string(path).map_err(|e| {
+                git2::Error::from_str(&format!(
+                    "Could not read SSH key passphrase file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            Ok(Some(passphrase.trim_end_matches(['\r', '\n']).to_string()))
+        } else {
+            Ok(self.ssh_passphrase.clone())
+        }
+    }
+
+    fn password(&self) -> std::result::Result<String, git2::Error> {
+        let path = self
+            .password_file
+            .as_ref()
+            .ok_or_else(|| git2::Error::from_str("No password file given"))?;
+        let password = if path == Path::new("-") {
+            // stdin can only be read once, but every operation asks
+            STDIN_PASSWORD
+                .get_or_init(|| {
+                    let mut password = String::new();
+                    std::io::stdin().read_line(&mut password).map(|_| password)
+                })
+                .as_ref()
+                .map_err(|e| e.to_string())
+                .cloned()
+        } else {
+            fs::read_to_string(path).map_err(|e| e.to_string())
+        };
+        let password = password.map_err(|e| {
+            git2::Error::from_str(&format!(
+                "Could not read the password from {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(password.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn netrc_file(&self) -> Option<PathBuf> {
+        if let Some(netrc_file) = &self.netrc_file {
+            Some(netrc_file.clone())
+        } else if let Some(netrc_file) = std::env::var_os("NETRC") {
+            Some(PathBuf::from(netrc_file))
+        } else {
+            std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".netrc"))
+        }
+    }
+
+    fn azure_devops_token(&self) -> Option<String> {
+        if self.azure_devops_token.is_some() {
+            return self.azure_devops_token.clone();
+        }
+        std::env::var_os("TF_BUILD")?;
+        let token = std::env::var("SYSTEM_ACCESSTOKEN").ok()?;
+        AZURE_PIPELINES_TOKEN_LOGGED.call_once(|| {
+            info!("Using $SYSTEM_ACCESSTOKEN of the Azure Pipelines job")
+        });
+        Some(token)
+    }
+
+    fn gitlab_job_token(&self) -> Option<String> {
+        if self.gitlab_job_token.is_some() {
+            return self.gitlab_job_token.clone();
+        }
+        if std::env::var("GITLAB_CI").ok()? != "true" {
+            return None;
+        }
+        let token = std::env::var("CI_JOB_TOKEN").ok()?;
+        GITLAB_JOB_TOKEN_LOGGED
+            .call_once(|| info!("Using $CI_JOB_TOKEN of the GitLab CI job"));
+        Some(token)
+    }
+
+    /// Job tokens are only valid on the GitLab instance that runs the job,
+    /// so warn early instead of failing with an authentication error
+    pub fn check_gitlab_host(&self, url: &str) {
+        if self.gitlab_job_token().is_none() {
+            return;
+        }
+        let server_url = match std::env::var("CI_SERVER_URL") {
+            Ok(server_url) => server_url,
+            Err(_) => return,
+        };
+        let server_host = git_url::host(&server_url);
+        let host = git_url::host(url);
+        if server_host.is_some() && host.is_some() && host != server_host {
+            warn!(
+                "{} is not on {}, the GitLab job token will probably be \
+                rejected",
+                redact::urls(url),
+                server_url
+            );
+        }
+    }
+
+    /// Builds the authorization headers for `--token` and
+    /// `--azure-devops-token`, depending on what the host of `url` expects
+    pub fn token_headers(&self, url: &str) -> Vec<String> {
+        self.headers_for_token(self.token.as_deref(), url)
+    }
+
+    /// Like `token_headers`, but with `--push-token` if it is given
+    pub fn push_token_headers(&self, url: &str) -> Vec<String> {
+        self.headers_for_token(self.push_token(), url)
+    }
+
+    fn push_token(&self) -> Option<&str> {
+        self.push_token.as_deref().or(self.token.as_deref())
+    }
+
+    fn headers_for_token(&self, token: Option<&str>, url: &str) -> Vec<String> {
+        let host = git_url::host(url).unwrap_or_default();
+        let is_azure_devops =
+            host == "dev.azure.com" || host.ends_with(".visualstudio.com");
+        let mut headers = vec![];
+        let token = token.filter(|_| self.sends_token_to(url));
+        if let Some(token) = token {
+            headers.push(match self.token_type {
+                Some(TokenType::Basic) => basic_header(token),
+                Some(TokenType::Bearer) => bearer_header(token),
+                None if is_azure_devops => azure_devops_header(token),
+                None => bearer_header(token),
+            });
+        }
+        if is_azure_devops {
+            headers.extend(
+                self.azure_devops_token()
+                    .map(|token| azure_devops_header(&token)),
+            );
+        }
+        headers
+    }
+
+    /// Replaces the opaque libgit2 SSH errors with a hint about what is
+    /// probably wrong
+    pub fn explain(&self, error: git2::Error) -> Error {
+        if error.code() == ErrorCode::Auth {
+            PipelineError::Transport(format!(
+                "Authentication failed: {}",
+                error.message()
+            ))
+        } else if error.class() == ErrorClass::Http
+            && error.message().contains("404")
+        {
+            PipelineError::Transport(format!(
+                "Repository not found: {}",
+                error.message()
+            ))
+        } else if error.class() == ErrorClass::Ssh
+            && error.message().contains("passphrase")
+        {
+            PipelineError::Transport(format!(
+                "Could not load the SSH key, the passphrase is wrong or the \
+                key format is not supported: {}",
+                error.message()
+            ))
+        } else {
+            error.into()
+        }
+    }
+}
+
+/// The basic auth header Azure DevOps expects for access tokens
+fn azure_devops_header(token: &str) -> String {
+    let credentials = base64::encode(format!(":{}", token));
+    format!("AUTHORIZATION: basic {}", credentials)
+}
+
+/// The header for `--token-type basic`, tokens without a user are sent like
+/// for Azure DevOps
+fn basic_header(token: &str) -> String {
+    if token.contains(':') {
+        format!("AUTHORIZATION: basic {}", base64::encode(token))
+    } else {
+        azure_devops_header(token)
+    }
+}
+
+fn bearer_header(token: &str) -> String {
+    format!("AUTHORIZATION: Bearer {}", token)
+}
+
+/// Hands out credentials to libgit2. libgit2 calls the credential callback
+/// again after every rejected attempt, so the authenticator remembers which
+/// mechanisms were already tried and moves on to the next one.
+struct Authenticator<'a> {
+    args: &'a AuthArgs,
+    /// Either `--token` or `--push-token`
+    token: Option<&'a str>,
+    tried_deploy_key: bool,
+    tried_agent: bool,
+    ssh_keys: Vec<PathBuf>,
+    tried_token: bool,
+    tried_gitlab_job_token: bool,
+    tried_github_app: bool,
+    tried_password: bool,
+    tried_credential_helper: bool,
+    /// Credentials from the helper that wait for the server's verdict
+    helper_credential: Option<(String, Credential)>,
+    tried_netrc: bool,
+}
+
+impl<'a> Authenticator<'a> {
+    fn new(args: &'a AuthArgs, token: Option<&'a str>) -> Self {
+        let mut ssh_keys = args.ssh_keys();
+        // keys are popped from the back
+        ssh_keys.reverse();
+        Self {
+            args,
+            token,
+            tried_deploy_key: false,
+            tried_agent: !args.use_ssh_agent(),
+            ssh_keys,
+            tried_token: false,
+            tried_gitlab_job_token: false,
+            tried_github_app: false,
+            tried_password: false,
+            tried_credential_helper: false,
+            helper_credential: None,
+            tried_netrc: false,
+        }
+    }
+
+    fn credentials(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        allowed_types: CredentialType,
+    ) -> std::result::Result<Cred, git2::Error> {
+        // being asked again means the last credentials were rejected
+        if let Some((url, credential)) = self.helper_credential.take() {
+            trace!(
+                "Rejecting credentials from git credential for {}",
+                redact::urls(&url)
+            );
+            if let Err(e) = credential_helper::reject(&url, &credential) {
+                trace!(
+                    "Could not reject credentials for {}: {}",
+                    redact::urls(&url),
+                    e
+                );
+            }
+        }
+
+        let log_url = redact::urls(url);
+        trace!("{} accepts {:?}", log_url, allowed_types);
+
+        // SSH remotes without a user ask for the username first
+        if allowed_types.contains(CredentialType::USERNAME) {
+            let username = username_from_url.unwrap_or("git");
+            trace!("Trying username {} for {}", username, log_url);
+            return Cred::username(username);
+        }
+
+        if allowed_types.contains(CredentialType::SSH_KEY) {
+            let username = username_from_url.ok_or_else(|| {
+                git2::Error::from_str(&format!(
+                    "Could not determine the SSH username for {}",
+                    log_url
+                ))
+            })?;
+
+            if !self.tried_deploy_key {
+                self.tried_deploy_key = true;
+                if let Some(deploy_key) = self.args.deploy_key(url) {
+                    trace!(
+                        "Trying deploy key {} for {}",
+                        deploy_key.display(),
+                        log_url
+                    );
+                    return self
+                        .ssh_key_credentials(username, deploy_key, None);
+                }
+            }
+
+            if !self.tried_agent {
+                self.tried_agent = true;
+                trace!("Trying ssh-agent for {}", log_url);
+                return Cred::ssh_key_from_agent(username);
+            }
+
+            if let Some(ssh_key) = self.ssh_keys.pop() {
+                trace!("Trying SSH key {} for {}", ssh_key.display(), log_url);
+                return self.ssh_key_credentials(
+                    username,
+                    &ssh_key,
+                    self.args.ssh_public_key.as_deref(),
+                );
+            }
+        }
+
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && !self.tried_token
+        {
+            self.tried_token = true;
+            // servers that ignore the bearer header still ask for basic auth
+            let token = self.token.filter(|_| self.args.sends_token_to(url));
+            if let Some(token) = token {
+                let username = username_from_url.unwrap_or("x-access-token");
+                trace!(
+                    "Trying --token as password of {} for {}",
+                    username,
+                    log_url
+                );
+                return Cred::userpass_plaintext(username, token);
+            }
+        }
+
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && !self.tried_gitlab_job_token
+        {
+            self.tried_gitlab_job_token = true;
+            if let Some(token) = self.args.gitlab_job_token() {
+                trace!("Trying GitLab job token for {}", log_url);
+                return Cred::userpass_plaintext("gitlab-ci-token", &token);
+            }
+        }
+
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && self.args.github_app.is_enabled()
+            && !self.tried_github_app
+        {
+            self.tried_github_app = true;
+            trace!("Trying GitHub App installation token for {}", log_url);
+            let token = self.args.github_app.token(url).map_err(|e| {
+                git2::Error::from_str(&format!(
+                    "Could not create a GitHub App installation token: {}",
+                    e
+                ))
+            })?;
+            return Cred::userpass_plaintext("x-access-token", &token);
+        }
+
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && !self.tried_password
+        {
+            self.tried_password = true;
+            if let Some(username) = &self.args.username {
+                trace!("Trying password of {} for {}", username, log_url);
+                return Cred::userpass_plaintext(
+                    username,
+                    &self.args.password()?,
+                );
+            }
+        }
+
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && self.args.use_credential_helper
+            && !self.tried_credential_helper
+        {
+            self.tried_credential_helper = true;
+            trace!("Trying git credential for {}", log_url);
+            match credential_helper::fill(url) {
+                Ok(Some(credential)) => {
+                    let cred = Cred::userpass_plaintext(
+                        &credential.username,
+                        &credential.password,
+                    );
+                    self.helper_credential =
+                        Some((url.to_string(), credential));
+                    return cred;
+                }
+                Ok(None) => {
+                    trace!("git credential has no credentials for {}", log_url)
+                }
+                Err(e) => {
+                    trace!("git credential failed for {}: {}", log_url, e)
+                }
+            }
+        }
+
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && !self.tried_netrc
+        {
+            self.tried_netrc = true;
+            if let Some(cred) = self.netrc_credentials(url, username_from_url) {
+                return cred;
+            }
+        }
+
+        Err(git2::Error::new(
+            ErrorCode::Auth,
+            ErrorClass::Callback,
+            format!("No more credentials to try for {}", log_url),
+        ))
+    }
+
+    fn netrc_credentials(
+        &self,
+        url: &str,
+        username_from_url: Option<&str>,
+    ) -> Option<std::result::Result<Cred, git2::Error>> {
+        let netrc_file = self.args.netrc_file()?;
+        if !netrc_file.exists() {
+            return None;
+        }
+        let host = git_url::host(url)?;
+        trace!("Looking up {} in {}", host, netrc_file.display());
+        let entry = match netrc::find(&netrc_file, host) {
+            Ok(entry) => entry?,
+            Err(e) => {
+                trace!("Ignoring {}: {}", netrc_file.display(), e);
+                return None;
+            }
+        };
+        let password = entry.password?;
+        let login = entry.login.as_deref().or(username_from_url)?;
+        Some(Cred::userpass_plaintext(login, &password))
+    }
+
+    fn ssh_key_credentials(
+        &self,
+        username: &str,
+        ssh_key: &Path,
+        ssh_public_key: Option<&Path>,
+    ) -> std::result::Result<Cred, git2::Error> {
+        if let Err(e) = fs::File::open(ssh_key) {
+            return Err(git2::Error::from_str(&format!(
+                "Could not read SSH key {}: {}",
+                ssh_key.display(),
+                e
+            )));
+        }
+        Cred::ssh_key(
+            username,
+            ssh_public_key,
+            ssh_key,
+            self.args.ssh_passphrase()?.as_deref(),
+        )
+    }
+}
+
+pub fn remote_callbacks(args: &AuthArgs) -> RemoteCallbacks<'_> {
+    callbacks_with_token(args, args.token.as_deref())
+}
+
+/// Like `remote_callbacks`, but with `--push-token` if it is given
+pub fn push_remote_callbacks(args: &AuthArgs) -> RemoteCallbacks<'_> {
+    callbacks_with_token(args, args.push_token())
+}
+
+fn callbacks_with_token<'a>(
+    args: &'a AuthArgs,
+    token: Option<&'a str>,
+) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut authenticator = Authenticator::new(args, token);
+    callbacks.credentials(move |url, username_from_url, allowed_types| {
+        authenticator.credentials(url, username_from_url, allowed_types)
+    });
+    callbacks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        auth: AuthArgs,
+    }
+
+    fn deploy_keys() -> AuthArgs {
+        Cli::parse_from([
+            "deploy",
+            "--deploy-key=gitlab.com=gitlab",
+            "--deploy-key=https://github.com/org=org",
+            "--deploy-key=git@github.com:other=other",
+        ])
+        .auth
+    }
+
+    #[test]
+    fn deploy_key_for_host_and_prefix() {
+        let args = deploy_keys();
+        let key = |url| args.deploy_key(url).and_then(|x| x.to_str());
+        assert_eq!(key("git@gitlab.com:group/repo.git"), Some("gitlab"));
+        assert_eq!(key("https://gitlab.com/group/repo.git"), Some("gitlab"));
+        assert_eq!(key("https://github.com/org/repo.git"), Some("org"));
+        assert_eq!(key("git@github.com:other/repo.git"), Some("other"));
+    }
+
+    #[test]
+    fn tokens_are_only_sent_to_allowed_hosts() {
+        let mut args = Cli::parse_from([
+            "deploy",
+            "--token=t0k3n",
+            "--token-host=Git.Example.com",
+        ])
+        .auth;
+        args.allow_token_for("https://github.com/org/composite.git");
+        let bearer = vec!["AUTHORIZATION: Bearer t0k3n".to_string()];
+        assert_eq!(args.token_headers("https://github.com/org/sub"), bearer);
+        assert_eq!(args.token_headers("git@github.com:org/sub.git"), bearer);
+        assert_eq!(args.token_headers("https://git.example.com/x"), bearer);
+        assert_eq!(args.push_token_headers("https://github.com/o/r"), bearer);
+        assert!(args.token_headers("https://gitlab.com/org/sub").is_empty());
+        assert!(args.token_headers("https://github.com.evil/org").is_empty());
+        assert!(args.push_token_headers("https://evil.com/o/r").is_empty());
+        assert!(args.token_headers("/local/path").is_empty());
+    }
+
+    #[test]
+    fn look_alike_urls_use_the_default_credentials() {
+        let args = deploy_keys();
+        assert_eq!(args.deploy_key("https://github.com/org-evil/repo"), None);
+        assert_eq!(args.deploy_key("https://github.com.attacker/org/x"), None);
+        assert_eq!(args.deploy_key("git@gitlab.com.attacker:group/x"), None);
+        assert_eq!(args.deploy_key("git@github.com:others/repo.git"), None);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::redact;
+use crate::result::{PipelineError, Result};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command};
+use serde::Serializer;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use toml::Value;
+
+/// Looked up in the working directory when `--config` is not given
+const LOCAL_CONFIG: &str = ".deployment.toml";
+
+/// Global options that take a value, needed to find the subcommand in the
+/// raw command line
+const GLOBAL_OPTIONS: &[&str] =
+    &["--output", "--config", "--log-format", "--color"];
+
+/// Options that only make sense on the command line
+const COMMAND_LINE_ONLY: &[&str] = &["config", "print_config"];
+
+/// Lists that the command line replaces instead of adding to them
+const REPLACED_LISTS: &[&str] = &["composite_repository"];
+
+/// Subcommands that do not take the options of the config file
+const WITHOUT_CONFIG: &[&str] = &["completions", "mangen", "version"];
+
+/// The config file given with `--config`, otherwise `./.deployment.toml` or
+/// `$XDG_CONFIG_HOME/deployment/config.toml` if they exist
+fn path(args: &[OsString]) -> Option<PathBuf> {
+    let explicit = args.iter().enumerate().find_map(|(index, arg)| {
+        let arg = arg.to_str()?;
+        if arg == "--config" {
+            args.get(index + 1).map(PathBuf::from)
+        } else {
+            arg.strip_prefix("--config=").map(PathBuf::from)
+        }
+    });
+    if explicit.is_some() {
+        return explicit;
+    }
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+        });
+    [
+        Some(PathBuf::from(LOCAL_CONFIG)),
+        config_home.map(|x| x.join("deployment").join("config.toml")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|x| x.is_file())
+}
+
+/// Position of the subcommand in the raw command line
+fn subcommand_index(args: &[OsString]) -> Option<usize> {
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let arg = arg.to_str()?;
+        if GLOBAL_OPTIONS.contains(&arg) {
+            index += 2;
+        } else if arg.starts_with('-') {
+            index += 1;
+        } else {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Turns one config value into command line arguments for `--long`
+fn value_args(
+    path: &Path,
+    key: &str,
+    long: &str,
+    value: &Value,
+) -> Result<Vec<OsString>> {
+    let arg = |value: String| OsString::from(format!("--{}={}", long, value));
+    match value {
+        Value::String(value) => Ok(vec![arg(value.clone())]),
+        Value::Integer(value) => Ok(vec![arg(value.to_string())]),
+        Value::Float(value) => Ok(vec![arg(value.to_string())]),
+        Value::Boolean(true) => Ok(vec![OsString::from(format!("--{}", long))]),
+        Value::Boolean(false) => Ok(Vec::new()),
+        Value::Array(values) => {
+            let mut args = Vec::new();
+            for value in values {
+                if matches!(value, Value::Array(_) | Value::Table(_)) {
+                    return Err(PipelineError::InvalidArguments(format!(
+                        "{}: {} must be a list of plain values",
+                        path.display(),
+                        key
+                    )));
+                }
+                args.extend(value_args(path, key, long, value)?);
+            }
+            Ok(args)
+        }
+        Value::Datetime(_) | Value::Table(_) => {
+            Err(PipelineError::InvalidArguments(format!(
+                "{}: unsupported value for {}",
+                path.display(),
+                key
+            )))
+        }
+    }
+}
+
+/// Whether the kind of `value` fits `arg`, so a mistyped value is reported
+/// for the config file instead of as a strange command line
+fn check_type(arg: &Arg, value: &Value) -> std::result::Result<(), String> {
+    let takes_values = arg.get_action().takes_values();
+    let optional_value =
+        arg.get_num_args().is_some_and(|x| x.min_values() == 0);
+    match value {
+        Value::Boolean(_) if takes_values && !optional_value => {
+            Err("expects a value, not true or false".to_string())
+        }
+        Value::Boolean(_) => Ok(()),
+        _ if !takes_values => Err("must be true or false".to_string()),
+        Value::Array(_) if !matches!(arg.get_action(), ArgAction::Append) => {
+            Err("takes a single value, not a list".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `subcommand` without its binary name and required options, to parse a
+/// part of its arguments
+fn relaxed(subcommand: &Command) -> Command {
+    let mut relaxed = subcommand.clone().no_binary_name(true);
+    let ids: Vec<_> = relaxed
+        .get_arguments()
+        .map(|x| x.get_id().clone())
+        .collect();
+    for id in ids {
+        relaxed = relaxed.mut_arg(id, |x| x.required(false));
+    }
+    relaxed
+}
+
+/// Whether `id` is given in `args`, the command line after the subcommand
+fn on_command_line(subcommand: &Command, args: &[OsString], id: &str) -> bool {
+    relaxed(subcommand)
+        .try_get_matches_from(args)
+        .is_ok_and(|x| x.value_source(id) == Some(ValueSource::CommandLine))
+}
+
+/// Parses the options for one key on their own to report invalid values
+/// like a non-numeric `depth` with the key they come from
+fn check_values(
+    subcommand: &Command,
+    args: &[OsString],
+) -> std::result::Result<(), String> {
+    match relaxed(subcommand).try_get_matches_from(args) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::InvalidValue | ErrorKind::ValueValidation
+            ) =>
+        {
+            let message = e.to_string();
+            let first_line = message.lines().next().unwrap_or_default();
+            Err(first_line.trim_start_matches("error: ").to_string())
+        }
+        // missing and conflicting options may be given on the command line
+        _ => Ok(()),
+    }
+}
+
+/// Inserts the options of the config file right after the subcommand, so
+/// options given on the command line override them. List options like
+/// `custom_headers` are combined instead, except for `REPLACED_LISTS`.
+pub fn merge(
+    mut command: Command,
+    args: Vec<OsString>,
+) -> Result<Vec<OsString>> {
+    let (path, index) = match (path(&args), subcommand_index(&args)) {
+        (Some(path), Some(index)) => (path, index),
+        _ => return Ok(args),
+    };
+    command.build();
+    let subcommand = match args[index]
+        .to_str()
+        .filter(|name| !WITHOUT_CONFIG.contains(name))
+        .and_then(|name| command.find_subcommand(name))
+    {
+        Some(subcommand) => subcommand,
+        None => return Ok(args),
+    };
+
+    let content = fs::read_to_string(&path).map_err(|e| {
+        PipelineError::InvalidArguments(format!("{}: {}", path.display(), e))
+    })?;
+    let table: toml::value::Table = toml::from_str(&content).map_err(|e| {
+        PipelineError::InvalidArguments(format!("{}: {}", path.display(), e))
+    })?;
+
+    let error = |message: String| {
+        PipelineError::InvalidArguments(format!(
+            "{}: {}",
+            path.display(),
+            message
+        ))
+    };
+    let mut config_args = Vec::new();
+    for (key, value) in &table {
+        let (arg, long) = subcommand
+            .get_arguments()
+            .filter(|arg| !COMMAND_LINE_ONLY.contains(&arg.get_id().as_str()))
+            .find(|arg| arg.get_id() == key.as_str())
+            .and_then(|arg| Some((arg, arg.get_long()?)))
+            .ok_or_else(|| error(format!("unknown option {}", key)))?;
+        check_type(arg, value).map_err(|e| error(format!("{} {}", key, e)))?;
+        if REPLACED_LISTS.contains(&key.as_str())
+            && on_command_line(subcommand, &args[index + 1..], key)
+        {
+            continue;
+        }
+        let args = value_args(&path, key, long, value)?;
+        check_values(subcommand, &args)
+            .map_err(|e| error(format!("{}: {}", key, e)))?;
+        config_args.extend(args);
+    }
+
+    let mut merged = args;
+    merged.splice(index + 1..index + 1, config_args);
+    Ok(merged)
+}
+
+/// Serializes the fully merged options for `--print-config`
+pub fn print<T: serde::Serialize>(args: &T) -> Result<String> {
+    toml::to_string(args).map_err(|e| PipelineError::Other(e.to_string()))
+}
+
+/// Hides credentials in `--print-config`
+pub fn secret<S: Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(_) => serializer.serialize_some("***"),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Hides credentials of headers in `--print-config`
+pub fn headers<S: Serializer>(
+    headers: &[String],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(headers.iter().map(|x| redact::header(x)))
+}
+
+/// Hides credentials embedded in a URL in `--print-config`
+pub fn url<S: Serializer>(
+    url: &str,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&redact::urls(url))
+}
+
+/// Hides credentials embedded in a list of URLs in `--print-config`
+pub fn urls<S: Serializer>(
+    urls: &[String],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(urls.iter().map(|x| redact::urls(x)))
+}
+
+/// Hides credentials embedded in an optional URL in `--print-config`
+pub fn optional_url<S: Serializer>(
+    url: &Option<String>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match url {
+        Some(url) => serializer.serialize_some(&redact::urls(url)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Options with an optional value are written as `true` when given without
+/// one or with an empty one from the environment, like in the config file
+pub fn optional_value<S: Serializer>(
+    value: &Option<Option<String>>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(Some(value)) if !value.is_empty() => {
+            serializer.serialize_some(value)
+        }
+        Some(_) => serializer.serialize_some(&true),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// `PREFIX=VALUE` pairs are written like they are given
+pub fn pairs<S: Serializer>(
+    pairs: &[(String, PathBuf)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        pairs
+            .iter()
+            .map(|(prefix, path)| format!("{}={}", prefix, path.display())),
+    )
+}
+
+/// `FROM=TO` mappings are written like they are given
+pub fn mappings<S: Serializer>(
+    mappings: &[(String, String)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        mappings.iter().map(|(from, to)| format!("{}={}", from, to)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn command() -> Command {
+        Command::new("deploy").subcommand(
+            Command::new("pipeline")
+                .arg(
+                    Arg::new("depth")
+                        .long("depth")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(Arg::new("recursive").long("recursive").num_args(0))
+                .arg(
+                    Arg::new("custom_headers")
+                        .long("custom-headers")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("composite_repository")
+                        .long("composite-repository")
+                        .action(ArgAction::Append),
+                ),
+        )
+    }
+
+    /// Merges `config` into `args`, returns the merged arguments
+    fn merge_config(config: &str, args: &[&str]) -> Result<Vec<String>> {
+        let directory = tempdir()?;
+        let path = directory.path().join("config.toml");
+        fs::write(&path, config)?;
+        let mut command_line = vec![
+            OsString::from("deploy"),
+            OsString::from("--config"),
+            path.clone().into(),
+            OsString::from("pipeline"),
+        ];
+        command_line.extend(args.iter().map(OsString::from));
+        let merged = merge(command(), command_line)?;
+        Ok(merged[4..]
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect())
+    }
+
+    #[test]
+    fn config_values_come_before_the_command_line() {
+        let merged = merge_config(
+            "depth = 5\nrecursive = true\ncustom_headers = [\"A: 1\"]\n",
+            &["--depth", "7", "--custom-headers", "B: 2"],
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            [
+                "--custom-headers=A: 1",
+                "--depth=5",
+                "--recursive",
+                "--depth",
+                "7",
+                "--custom-headers",
+                "B: 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_replaces_replaced_lists() {
+        let config = "composite_repository = [\"a\", \"b\"]\n";
+        assert_eq!(
+            merge_config(config, &[]).unwrap(),
+            ["--composite-repository=a", "--composite-repository=b"]
+        );
+        assert_eq!(
+            merge_config(config, &["--composite-repository", "c"]).unwrap(),
+            ["--composite-repository", "c"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_values_with_their_key() {
+        let error = |config| merge_config(config, &[]).unwrap_err().to_string();
+        assert!(error("colour = true\n").contains("unknown option colour"));
+        assert!(error("recursive = \"yes\"\n")
+            .contains("recursive must be true or false"));
+        assert!(error("depth = true\n")
+            .contains("depth expects a value, not true or false"));
+        assert!(error("depth = [1, 2]\n")
+            .contains("depth takes a single value, not a list"));
+        assert!(error("depth = \"deep\"\n").contains("depth: invalid value"));
+    }
+}
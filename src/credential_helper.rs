@@ -0,0 +1,69 @@
+use crate::result::Result;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Credentials returned by `git credential fill`
+pub struct Credential {
+    pub username: String,
+    pub password: String,
+}
+
+/// Runs `git credential <action>` and feeds `input` as its description of
+/// the credential
+fn git_credential(action: &str, input: &str) -> Result<String> {
+    let mut child = Command::new("git")
+        .args(["credential", action])
+        // never block a pipeline on a password prompt
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or("Could not write to git credential")?
+        .write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "git credential {} failed: {}",
+            action, output.status
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Asks the configured credential helpers for credentials for `url`
+pub fn fill(url: &str) -> Result<Option<Credential>> {
+    let output = git_credential("fill", &format!("url={}\n\n", url))?;
+    let mut username = None;
+    let mut password = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => username = Some(value.to_string()),
+            Some(("password", value)) => password = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Ok(match (username, password) {
+        (Some(username), Some(password)) => {
+            Some(Credential { username, password })
+        }
+        _ => None,
+    })
+}
+
+/// Tells the credential helpers that `credential` was rejected for `url`,
+/// so they drop it from their cache
+pub fn reject(url: &str, credential: &Credential) -> Result<()> {
+    git_credential(
+        "reject",
+        &format!(
+            "url={}\nusername={}\npassword={}\n\n",
+            url, credential.username, credential.password
+        ),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::redact;
+use crate::result::Result;
+use git2::Config;
+use log::trace;
+
+/// Returns the host of a git URL. Both `scheme://[user@]host[:port]/path`
+/// and the scp-like `[user@]host:path` syntax are understood, local paths have
+/// no host.
+pub fn host(url: &str) -> Option<&str> {
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest,
+        None => {
+            let colon = url.find(':')?;
+            if url[..colon].contains('/') {
+                return None;
+            }
+            url
+        }
+    };
+    let authority = rest.split('/').next()?;
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, x)| x);
+    authority.split(':').next().filter(|host| !host.is_empty())
+}
+
+/// Whether `url` is `prefix` or below it, the way git matches the URL of
+/// `http.<url>.*`: the prefix has to end at a `/` or `:` of the URL, so
+/// `https://host` does not match `https://host.example`
+pub fn has_prefix(url: &str, prefix: &str) -> bool {
+    match url.strip_prefix(prefix) {
+        Some(rest) => {
+            rest.is_empty()
+                || prefix.ends_with(['/', ':'])
+                || rest.starts_with(['/', ':'])
+        }
+        None => false,
+    }
+}
+
+/// Returns the path of a git URL, see `host` for the supported syntaxes
+pub fn path(url: &str) -> Option<&str> {
+    match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map(|index| &rest[index..]),
+        None => {
+            let colon = url.find(':')?;
+            if url[..colon].contains('/') {
+                Some(url)
+            } else {
+                Some(&url[colon + 1..])
+            }
+        }
+    }
+}
+
+/// Reduces a URL to what identifies the repository, so the HTTPS and SSH
+/// URLs of one repository compare equal: the scheme, user, port, trailing
+/// slashes and `.git` are dropped and the host is lower case
+pub fn normalize(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = url
+        .strip_suffix(".git")
+        .unwrap_or(url)
+        .trim_end_matches('/');
+    match (host(url), path(url)) {
+        (Some(host), Some(path)) => format!(
+            "{}/{}",
+            host.to_ascii_lowercase(),
+            path.trim_start_matches('/')
+        ),
+        _ => url.strip_prefix("file://").unwrap_or(url).to_string(),
+    }
+}
+
+/// Applies the `url.<base>.insteadOf` rules of `config` like git does, the
+/// longest matching prefix wins. For pushes `pushInsteadOf` takes precedence.
+pub fn rewrite(config: &Config, url: &str, push: bool) -> Result<String> {
+    let mut rewritten = None;
+    if push {
+        rewritten = longest_match(config, url, "pushinsteadof")?;
+    }
+    if rewritten.is_none() {
+        rewritten = longest_match(config, url, "insteadof")?;
+    }
+    Ok(match rewritten {
+        Some(rewritten) => {
+            trace!(
+                "Rewrote {} to {}",
+                redact::urls(url),
+                redact::urls(&rewritten)
+            );
+            rewritten
+        }
+        None => url.to_string(),
+    })
+}
+
+fn longest_match(
+    config: &Config,
+    url: &str,
+    variable: &str,
+) -> Result<Option<String>> {
+    let mut best: Option<(usize, String)> = None;
+    let pattern = format!(r"^url\..*\.{}$", variable);
+    let mut entries = config.entries(Some(&pattern))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let (name, prefix) = match (entry.name(), entry.value()) {
+            (Some(name), Some(prefix)) => (name, prefix),
+            _ => continue,
+        };
+        // the base may contain dots, only the first and last are separators
+        let base = &name["url.".len()..name.len() - variable.len() - 1];
+        if url.starts_with(prefix)
+            && best
+                .as_ref()
+                .is_none_or(|(length, _)| prefix.len() > *length)
+        {
+            best = Some((
+                prefix.len(),
+                format!("{}{}", base, &url[prefix.len()..]),
+            ));
+        }
+    }
+    Ok(best.map(|(_, rewritten)| rewritten))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn config(content: &str) -> Config {
+        let directory = tempdir().unwrap();
+        let path = directory.path().join("config");
+        fs::write(&path, content).unwrap();
+        // the snapshot is read into memory, so the directory can be removed
+        let mut config = Config::open(&path).unwrap();
+        config.snapshot().unwrap()
+    }
+
+    #[test]
+    fn rewrites_longest_prefix() {
+        let config = config(
+            "[url \"https://token@host/\"]\n\
+            \tinsteadOf = ssh://git@host/\n\
+            [url \"https://token@host/org/\"]\n\
+            \tinsteadOf = ssh://git@host/org/\n",
+        );
+        assert_eq!(
+            rewrite(&config, "ssh://git@host/repo.git", false).unwrap(),
+            "https://token@host/repo.git"
+        );
+        assert_eq!(
+            rewrite(&config, "ssh://git@host/org/repo.git", false).unwrap(),
+            "https://token@host/org/repo.git"
+        );
+        assert_eq!(
+            rewrite(&config, "https://other/repo.git", false).unwrap(),
+            "https://other/repo.git"
+        );
+    }
+
+    #[test]
+    fn push_instead_of_only_for_pushes() {
+        let config = config(
+            "[url \"https://mirror/\"]\n\
+            \tinsteadOf = https://host/\n\
+            [url \"ssh://git@host/\"]\n\
+            \tpushInsteadOf = https://host/\n",
+        );
+        assert_eq!(
+            rewrite(&config, "https://host/repo.git", false).unwrap(),
+            "https://mirror/repo.git"
+        );
+        assert_eq!(
+            rewrite(&config, "https://host/repo.git", true).unwrap(),
+            "ssh://git@host/repo.git"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::git_url;
+use crate::result::Result;
+use clap::Args;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use log::{info, trace};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Installation tokens are valid for an hour, refresh them a bit earlier
+const TOKEN_LIFETIME: Duration = Duration::from_secs(55 * 60);
+
+/// The installation token is shared by all network operations of a run
+static TOKEN: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+#[derive(Args, Serialize, Debug, Clone)]
+pub struct GithubAppArgs {
+    /// ID of the GitHub App to authenticate as
+    #[arg(
+        long,
+        requires = "github_app_key_file",
+        env = "DEPLOYMENT_GITHUB_APP_ID"
+    )]
+    github_app_id: Option<u64>,
+
+    /// Private key of the GitHub App
+    #[arg(
+        long,
+        requires = "github_app_id",
+        env = "DEPLOYMENT_GITHUB_APP_KEY_FILE"
+    )]
+    github_app_key_file: Option<PathBuf>,
+
+    /// Installation of the GitHub App [default: the installation on the owner
+    /// of the composite repository]
+    #[arg(
+        long,
+        requires = "github_app_id",
+        env = "DEPLOYMENT_GITHUB_APP_INSTALLATION_ID"
+    )]
+    github_app_installation_id: Option<u64>,
+
+    /// Base URL of the GitHub API
+    #[arg(
+        long,
+        default_value = "https://api.github.com",
+        env = "DEPLOYMENT_GITHUB_API_URL"
+    )]
+    github_api_url: String,
+}
+
+#[derive(Serialize)]
+struct Claims {
+    iat: u64,
+    exp: u64,
+    iss: u64,
+}
+
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    token: String,
+}
+
+impl GithubAppArgs {
+    pub fn is_enabled(&self) -> bool {
+        self.github_app_id.is_some()
+    }
+
+    /// Returns an installation token, exchanging a new one if there is none
+    /// yet or the old one is about to expire. `url` is the remote that is
+    /// accessed, it is used to discover the installation.
+    pub fn token(&self, url: &str) -> Result<String> {
+        let mut cached = TOKEN.lock().unwrap();
+        if let Some((token, valid_until)) = &*cached {
+            if Instant::now() < *valid_until {
+                return Ok(token.clone());
+            }
+            trace!("GitHub App installation token expired");
+        }
+
+        let jwt = self.jwt()?;
+        let installation_id = match self.github_app_installation_id {
+            Some(id) => id,
+            None => self.discover_installation(&jwt, url)?,
+        };
+        info!(
+            "Creating token for GitHub App installation {}",
+            installation_id
+        );
+        let access_token: AccessToken = ureq::post(&format!(
+            "{}/app/installations/{}/access_tokens",
+            self.github_api_url, installation_id
+        ))
+        .set("Authorization", &format!("Bearer {}", jwt))
+        .set("Accept", "application/vnd.github+json")
+        .call()?
+        .into_json()?;
+
+        *cached =
+            Some((access_token.token.clone(), Instant::now() + TOKEN_LIFETIME));
+        Ok(access_token.token)
+    }
+
+    fn jwt(&self) -> Result<String> {
+        let app_id = self.github_app_id.ok_or("No GitHub App id given")?;
+        let key_file = self
+            .github_app_key_file
+            .as_ref()
+            .ok_or("No GitHub App key file given")?;
+        let key = fs::read(key_file).map_err(|e| {
+            format!("Could not read {}: {}", key_file.display(), e)
+        })?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        // allow some clock drift towards GitHub
+        let claims = Claims {
+            iat: now - 60,
+            exp: now + 9 * 60,
+            iss: app_id,
+        };
+        Ok(jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &EncodingKey::from_rsa_pem(&key)?,
+        )?)
+    }
+
+    fn discover_installation(&self, jwt: &str, url: &str) -> Result<u64> {
+        let path = git_url::path(url).ok_or("Remote URL has no path")?;
+        let mut segments = path.trim_matches('/').split('/');
+        let (owner, repo) = match (segments.next(), segments.next()) {
+            (Some(owner), Some(repo)) => (owner, repo.trim_end_matches(".git")),
+            _ => {
+                return Err(format!(
+                    "Cannot determine the repository owner of {}",
+                    url
+                )
+                .into())
+            }
+        };
+        trace!("Discovering GitHub App installation for {}/{}", owner, repo);
+        let installation: Installation = ureq::get(&format!(
+            "{}/repos/{}/{}/installation",
+            self.github_api_url, owner, repo
+        ))
+        .set("Authorization", &format!("Bearer {}", jwt))
+        .set("Accept", "application/vnd.github+json")
+        .call()?
+        .into_json()?;
+        Ok(installation.id)
+    }
+}
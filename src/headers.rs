@@ -0,0 +1,117 @@
+use crate::git_url;
+use crate::result::Result;
+use std::fs;
+use std::path::Path;
+
+/// Splits a custom header into the URL prefix it is scoped to and the header
+/// itself. Scoped headers are written as `<url prefix>=<header>`, e.g.
+/// `https://dev.azure.com=AUTHORIZATION: basic xxx`.
+fn parse(value: &str) -> (Option<&str>, &str) {
+    match value.split_once('=') {
+        Some((prefix, header))
+            if prefix.contains("://")
+                && !prefix.contains(char::is_whitespace) =>
+        {
+            (Some(prefix), header)
+        }
+        _ => (None, value),
+    }
+}
+
+/// Selects the headers that are sent to `url`. Unscoped headers are sent to
+/// every remote, scoped ones to the URLs below their prefix.
+pub fn for_url<'a>(headers: &'a [String], url: &str) -> Vec<&'a str> {
+    headers
+        .iter()
+        .map(|value| parse(value))
+        .filter(|(prefix, _)| {
+            prefix.is_none_or(|x| git_url::has_prefix(url, x))
+        })
+        .map(|(_, header)| header)
+        .collect()
+}
+
+/// Reads custom headers from a file with one header per line. Blank lines and
+/// lines starting with `#` are ignored, scoped headers are supported.
+pub fn read_file(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut headers = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (_, header) = parse(line);
+        match header.split_once(':') {
+            Some((name, _))
+                if !name.is_empty() && !name.contains(char::is_whitespace) => {}
+            _ => {
+                return Err(format!(
+                    "{}:{}: expected a header like `Name: value`",
+                    path.display(),
+                    index + 1
+                )
+                .into())
+            }
+        }
+        headers.push(line.to_string());
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Vec<String> {
+        [
+            "X-Everywhere: 1",
+            "https://dev.azure.com=AUTHORIZATION: basic xxx",
+            "https://github.com/org/=X-Org: 1",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn scoped_to_exact_host() {
+        let headers = headers();
+        let expected = ["X-Everywhere: 1", "AUTHORIZATION: basic xxx"];
+        assert_eq!(for_url(&headers, "https://dev.azure.com"), expected);
+        assert_eq!(
+            for_url(&headers, "https://dev.azure.com/org/_git/repo"),
+            expected
+        );
+        assert_eq!(
+            for_url(&headers, "https://dev.azure.com:443/org/_git/repo"),
+            expected
+        );
+    }
+
+    #[test]
+    fn scoped_to_path_prefix() {
+        let headers = headers();
+        assert_eq!(
+            for_url(&headers, "https://github.com/org/repo.git"),
+            ["X-Everywhere: 1", "X-Org: 1"]
+        );
+        assert_eq!(
+            for_url(&headers, "https://github.com/other/repo.git"),
+            ["X-Everywhere: 1"]
+        );
+    }
+
+    #[test]
+    fn not_sent_to_look_alike_hosts() {
+        let headers = headers();
+        assert_eq!(
+            for_url(&headers, "https://dev.azure.com.evil.example/org/_git/x"),
+            ["X-Everywhere: 1"]
+        );
+        assert_eq!(
+            for_url(&headers, "https://github.com/org-evil/repo.git"),
+            ["X-Everywhere: 1"]
+        );
+    }
+}
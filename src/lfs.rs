@@ -0,0 +1,58 @@
+use crate::result::{PipelineError, Result};
+use git2::{Commit, ObjectType, Repository};
+use log::trace;
+use std::path::Path;
+use std::process::Command;
+
+/// Fails early if `git lfs` cannot be run
+pub fn check_installed() -> Result<()> {
+    match Command::new("git").args(["lfs", "version"]).output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout);
+            trace!("Using {}", version.trim());
+            Ok(())
+        }
+        _ => Err(PipelineError::InvalidArguments(
+            "--lfs requires git-lfs, which is not installed".to_string(),
+        )),
+    }
+}
+
+/// Whether the `.gitattributes` of `commit` route files through the LFS
+/// filter
+pub fn is_used(repository: &Repository, commit: &Commit) -> Result<bool> {
+    let tree = commit.tree()?;
+    let Some(entry) = tree.get_name(".gitattributes") else {
+        return Ok(false);
+    };
+    if entry.kind() != Some(ObjectType::Blob) {
+        return Ok(false);
+    }
+    let object = entry.to_object(repository)?;
+    let blob = object.peel_to_blob()?;
+    let attributes = String::from_utf8_lossy(blob.content());
+    Ok(attributes.lines().any(|line| {
+        !line.trim_start().starts_with('#')
+            && line.split_whitespace().any(|x| x == "filter=lfs")
+    }))
+}
+
+/// Replaces the pointer files in `workdir` with their content. `git lfs`
+/// authenticates on its own with the credential helpers of git.
+pub fn pull(workdir: &Path) -> Result<()> {
+    trace!("Running git lfs pull in {}", workdir.display());
+    let output = Command::new("git")
+        .args(["lfs", "pull"])
+        .current_dir(workdir)
+        .output()
+        .map_err(|e| format!("Could not run git lfs: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git lfs pull failed in {}: {}",
+            workdir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
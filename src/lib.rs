@@ -0,0 +1,30 @@
+//! Updates the submodules of a composite repository to the HEAD of a child
+//! repository and pushes the result. The `deploy` binary is a thin command
+//! line wrapper around [`Pipeline`].
+
+mod auth;
+pub mod config;
+mod credential_helper;
+mod git_url;
+mod github_app;
+mod headers;
+mod lfs;
+pub mod logging;
+mod netrc;
+pub mod output;
+mod patch;
+mod proxy;
+pub mod redact;
+pub mod result;
+mod signing;
+pub mod subcommands;
+mod timeout;
+mod tls;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+pub use crate::result::{PipelineError, Result};
+pub use crate::subcommands::pipeline::{
+    BatchOutcome, CompositesOutcome, Pipeline, PipelineOutcome,
+    RepositoryWrapper,
+};
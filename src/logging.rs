@@ -0,0 +1,73 @@
+use clap::ValueEnum;
+use env_logger::fmt::Formatter;
+use env_logger::WriteStyle;
+use log::kv::{self, VisitSource};
+use log::{LevelFilter, Record};
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+/// How log records are written to stderr
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Colored lines for humans
+    #[default]
+    Text,
+    /// One JSON object per record, including the `repository`,
+    /// `submodule` and `phase` fields of the record
+    Json,
+}
+
+/// Sets up logging at the level of `-v`, unless `RUST_LOG` is set
+pub fn init(verbose: u8, format: Format, use_color: bool) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    match std::env::var("RUST_LOG") {
+        Ok(filters) if !filters.is_empty() => {
+            builder.parse_filters(&filters);
+        }
+        _ => {
+            builder.filter_level(match verbose {
+                0 => LevelFilter::Error,
+                1 => LevelFilter::Info,
+                2 => LevelFilter::Debug,
+                _ => LevelFilter::Trace,
+            });
+        }
+    }
+    builder.write_style(if use_color {
+        WriteStyle::Always
+    } else {
+        WriteStyle::Never
+    });
+    if format == Format::Json {
+        builder.format(write_json);
+    }
+    builder.init();
+}
+
+/// Collects the key-values of a record as JSON fields
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: kv::Key<'kvs>,
+        value: kv::Value<'kvs>,
+    ) -> Result<(), kv::Error> {
+        self.0
+            .insert(key.to_string(), Value::String(value.to_string()));
+        Ok(())
+    }
+}
+
+fn write_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut fields = Map::new();
+    fields.insert("timestamp".into(), buf.timestamp().to_string().into());
+    fields.insert("level".into(), record.level().as_str().into());
+    fields.insert("target".into(), record.target().into());
+    fields.insert("message".into(), record.args().to_string().into());
+    record
+        .key_values()
+        .visit(&mut Fields(&mut fields))
+        .map_err(io::Error::other)?;
+    writeln!(buf, "{}", Value::Object(fields))
+}
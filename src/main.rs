@@ -1,13 +1,49 @@
-mod result;
-mod subcommands;
-
-use crate::result::Result;
-use clap::{Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
+use deployment_withlazers::subcommands::pipeline::CompositeOutcome;
+use deployment_withlazers::{
+    config, logging, output, redact, result, subcommands, Pipeline, Result,
+};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
+#[command(name = "deploy", version, after_help = result::EXIT_CODES)]
 struct Args {
-    #[arg(short, long)]
-    verbose: bool,
+    /// Log more, `-v` for info, `-vv` for debug and `-vvv` for trace
+    /// messages. RUST_LOG overrides it.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Format of the log on stderr
+    #[arg(long, value_enum, default_value_t, global = true)]
+    log_format: logging::Format,
+    /// Only print errors and the result, other messages are logged at info
+    /// level
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Fail instead of warning when falling back to something that may not
+    /// be intended, like an unknown default branch of the composite
+    /// repository or a submodule that cannot be opened
+    #[arg(long, global = true)]
+    strict: bool,
+    /// When to color messages and the log on stderr
+    #[arg(long, value_enum, default_value_t, global = true)]
+    color: output::Color,
+    /// Output format, `json` is only supported by `pipeline`,
+    /// `list-submodules` and `version`
+    #[arg(long, value_enum, default_value_t, global = true)]
+    output: output::Format,
+    /// Config file with defaults for the options of the subcommand, keys are
+    /// the option names with underscores. Command line options take
+    /// precedence over the file, which takes precedence over environment
+    /// variables and defaults. Lists like custom_headers are combined.
+    /// [default: ./.deployment.toml or
+    /// $XDG_CONFIG_HOME/deployment/config.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Print the options merged from the config file and the command line
+    /// with credentials hidden, then exit
+    #[arg(long, global = true)]
+    print_config: bool,
     #[command(subcommand)]
     subcommand: Action,
 }
@@ -15,13 +51,218 @@ struct Args {
 #[derive(Subcommand, Debug, Clone)]
 enum Action {
     Pipeline(subcommands::pipeline::Args),
+    /// Check whether the composite repository already points to the child
+    /// repository's HEAD, exits with 8 if an update is pending
+    Status(subcommands::pipeline::Args),
+    /// Point the submodule at --submodule-path back to the commit before its
+    /// last update and push that. Refuses if the last commit of the branch
+    /// changed more than submodules.
+    Rollback(subcommands::pipeline::Args),
+    /// Check the options without cloning anything: the child repository,
+    /// its ref and commit and access to the composite repository. Exits with
+    /// 9 if a check fails.
+    Validate(subcommands::pipeline::Args),
+    /// Check that the composite repository, its submodules and the push URL
+    /// can be accessed with the given credentials, exits with 9 otherwise
+    ValidateAuth(subcommands::pipeline::Args),
+    /// Print the name, path, URL and pinned commit of each submodule of the
+    /// composite repository, as JSON with `--output json`
+    ListSubmodules(subcommands::list_submodules::Args),
+    /// Print the version of the tool, its commit, build time and the
+    /// libgit2 version, as JSON with `--output json`
+    Version,
+    /// Print a shell completion script, e.g. `deploy completions zsh >
+    /// _deploy`
+    Completions(subcommands::completions::Args),
+    /// Write man pages for all subcommands, used for packaging
+    #[command(hide = true)]
+    Mangen(subcommands::mangen::Args),
+}
+
+/// The parser with the build metadata, which is only known at runtime
+fn command() -> clap::Command {
+    Args::command().long_version(subcommands::version::long_version())
+}
+
+fn main() {
+    let command_line =
+        match config::merge(command(), std::env::args_os().collect()) {
+            Ok(command_line) => command_line,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(e.exit_code());
+            }
+        };
+    let args = command()
+        .try_get_matches_from(command_line)
+        .and_then(|matches| Args::from_arg_matches(&matches))
+        .and_then(|args| {
+            // clap misses the conflict when they follow the subcommand
+            if args.quiet && args.verbose > 0 {
+                return Err(Args::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "the argument '--quiet' cannot be used with '--verbose'",
+                ));
+            }
+            Ok(args)
+        });
+    let args = match args {
+        Ok(args) => args,
+        // clap exits with 2 on usage errors, which is taken
+        Err(e) if e.use_stderr() => {
+            let _ = e.print();
+            std::process::exit(result::EXIT_INVALID_ARGUMENTS);
+        }
+        Err(e) => e.exit(),
+    };
+    let use_color = output::init_color(args.color);
+    logging::init(args.verbose, args.log_format, use_color);
+
+    let output = args.output;
+    if let Err(e) = run(args) {
+        // git2 errors may echo URLs with credentials
+        let message = redact::urls(&e.to_string());
+        match output {
+            output::Format::Text => output::error(&message),
+            output::Format::Json => {
+                println!("{}", serde_json::json!({ "error": message }))
+            }
+        }
+        std::process::exit(e.exit_code());
+    }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    pretty_env_logger::init();
+fn run(mut args: Args) -> Result<()> {
+    let options = match &mut args.subcommand {
+        Action::Pipeline(options)
+        | Action::Rollback(options)
+        | Action::Status(options)
+        | Action::Validate(options)
+        | Action::ValidateAuth(options) => Some(options),
+        Action::ListSubmodules(list) => Some(&mut list.pipeline),
+        _ => None,
+    };
+    if let Some(options) = options {
+        options.output = args.output;
+        options.quiet = args.quiet;
+        options.strict = args.strict;
+    }
+    if args.print_config {
+        let printed = match &args.subcommand {
+            Action::Pipeline(options)
+            | Action::Rollback(options)
+            | Action::Status(options)
+            | Action::Validate(options)
+            | Action::ValidateAuth(options) => Some(config::print(options)?),
+            Action::ListSubmodules(list) => Some(config::print(list)?),
+            _ => None,
+        };
+        if let Some(printed) = printed {
+            print!("{}", printed);
+            return Ok(());
+        }
+    }
 
+    // stdout is reserved for results, messages for humans go to stderr
     match args.subcommand {
-        Action::Pipeline(pipeline) => subcommands::pipeline::run(pipeline),
+        Action::Pipeline(pipeline) if pipeline.repositories_file.is_some() => {
+            let exit_if_up_to_date = pipeline.dry_run || pipeline.check;
+            let outcome = Pipeline::new(pipeline).run_batch()?;
+            print_result(args.output, &outcome.composite_commit, &outcome)?;
+            // the other child repositories were pushed nonetheless
+            if let Some((path, exit_code)) = outcome.failure() {
+                if args.output == output::Format::Text {
+                    output::error(&format!("Updating {} failed", path));
+                }
+                std::process::exit(exit_code);
+            }
+            if exit_if_up_to_date && !outcome.updated {
+                std::process::exit(result::EXIT_UP_TO_DATE);
+            }
+            Ok(())
+        }
+        Action::Pipeline(pipeline)
+            if pipeline.composite_repositories.len() > 1 =>
+        {
+            let exit_if_up_to_date = pipeline.dry_run || pipeline.check;
+            let outcome = Pipeline::new(pipeline).run_composites()?;
+            let commits: Vec<_> = outcome
+                .composites
+                .iter()
+                .filter_map(|x| match x {
+                    CompositeOutcome::Updated(outcome) => {
+                        Some(outcome.composite_commit.as_str())
+                    }
+                    CompositeOutcome::Failed { .. } => None,
+                })
+                .collect();
+            print_result(args.output, &commits.join("\n"), &outcome)?;
+            // the other composite repositories were pushed nonetheless
+            if let Some((url, exit_code)) = outcome.failure() {
+                if args.output == output::Format::Text {
+                    output::error(&format!("Updating {} failed", url));
+                }
+                std::process::exit(exit_code);
+            }
+            if exit_if_up_to_date && !outcome.updated {
+                std::process::exit(result::EXIT_UP_TO_DATE);
+            }
+            Ok(())
+        }
+        Action::Pipeline(pipeline) => {
+            let exit_if_up_to_date = pipeline.dry_run || pipeline.check;
+            let outcome = Pipeline::new(pipeline).run()?;
+            print_result(args.output, &outcome.composite_commit, &outcome)?;
+            // lets later steps of a pipeline depend on the dry run or --check
+            if exit_if_up_to_date && !outcome.updated {
+                std::process::exit(result::EXIT_UP_TO_DATE);
+            }
+            Ok(())
+        }
+        Action::Rollback(rollback) => {
+            println!("{}", subcommands::rollback::run(rollback)?);
+            Ok(())
+        }
+        Action::Status(status) => {
+            if !subcommands::status::run(status)? {
+                std::process::exit(result::EXIT_UPDATE_PENDING);
+            }
+            Ok(())
+        }
+        Action::Validate(validate) => {
+            if !subcommands::validate::run(validate)? {
+                std::process::exit(result::EXIT_CHECK_FAILED);
+            }
+            Ok(())
+        }
+        Action::ValidateAuth(validate_auth) => {
+            if !subcommands::validate_auth::run(validate_auth)? {
+                std::process::exit(result::EXIT_CHECK_FAILED);
+            }
+            Ok(())
+        }
+        Action::ListSubmodules(list) => {
+            let submodules = subcommands::list_submodules::run(list)?;
+            subcommands::list_submodules::print(args.output, &submodules)
+        }
+        Action::Version => subcommands::version::run(args.output),
+        Action::Mangen(mangen) => subcommands::mangen::run(mangen, command()),
+        Action::Completions(completions) => {
+            subcommands::completions::run(completions, Args::command());
+            Ok(())
+        }
+    }
+}
+
+/// Prints the composite commit, or the whole outcome with `--output json`
+fn print_result<T: serde::Serialize>(
+    output: output::Format,
+    composite_commit: &str,
+    outcome: &T,
+) -> Result<()> {
+    match output {
+        output::Format::Text => println!("{}", composite_commit),
+        output::Format::Json => println!("{}", serde_json::to_string(outcome)?),
     }
+    Ok(())
 }
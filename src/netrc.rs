@@ -0,0 +1,74 @@
+use crate::result::Result;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub login: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Looks up the entry for `host` in the netrc file at `path`, falling back to
+/// the `default` entry. Macro definitions are skipped.
+pub fn find(path: &Path, host: &str) -> Result<Option<Entry>> {
+    let content = fs::read_to_string(path)?;
+    let mut matching = None;
+    let mut default = None;
+    // the entry that is currently parsed and whether it is the one we want
+    let mut current: Option<(Entry, bool)> = None;
+    let mut in_macdef = false;
+
+    for line in content.lines() {
+        if in_macdef {
+            in_macdef = !line.trim().is_empty();
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" | "default" => {
+                    if let Some((entry, true)) = current.take() {
+                        matching.get_or_insert(entry);
+                    }
+                    if token == "default" {
+                        current = None;
+                        default.get_or_insert_with(Entry::default);
+                    } else {
+                        let machine = tokens.next().ok_or_else(|| {
+                            format!(
+                                "Missing machine name in {}",
+                                path.display()
+                            )
+                        })?;
+                        current = Some((Entry::default(), machine == host));
+                    }
+                }
+                "login" | "password" | "account" | "port" => {
+                    let value = tokens.next().ok_or_else(|| {
+                        format!("Missing {} value in {}", token, path.display())
+                    })?;
+                    let entry = match (&mut current, &mut default) {
+                        (Some((entry, _)), _) => entry,
+                        (None, Some(entry)) => entry,
+                        (None, None) => continue,
+                    };
+                    match token {
+                        "login" => entry.login = Some(value.to_string()),
+                        "password" => entry.password = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                "macdef" => {
+                    in_macdef = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some((entry, true)) = current {
+        matching.get_or_insert(entry);
+    }
+
+    Ok(matching.or(default))
+}
@@ -0,0 +1,80 @@
+use clap::ValueEnum;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How the result of a run is printed
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Progress messages for humans
+    #[default]
+    Text,
+    /// A single JSON object on stdout, messages go to the log
+    Json,
+}
+
+/// When messages and the log on stderr are colored
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Color {
+    /// If stderr is a terminal, NO_COLOR and CLICOLOR_FORCE are honored
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How a message for humans is highlighted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    /// A step of the run, like pushing
+    Step,
+    Success,
+    Warning,
+    Error,
+}
+
+static USE_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Decides once whether stderr is colored, see https://no-color.org and
+/// https://bixense.com/clicolors
+pub fn init_color(color: Color) -> bool {
+    let is_set = |name| env::var_os(name).is_some_and(|x| !x.is_empty());
+    let use_color = match color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto if is_set("NO_COLOR") => false,
+        Color::Auto if is_set("CLICOLOR_FORCE") => {
+            env::var_os("CLICOLOR_FORCE").is_some_and(|x| x != "0")
+        }
+        Color::Auto => io::stderr().is_terminal(),
+    };
+    USE_COLOR.store(use_color, Ordering::Relaxed);
+    use_color
+}
+
+/// `text` with the ANSI escapes of `style`, if stderr is colored
+pub fn paint(style: Style, text: &str) -> String {
+    let code = match style {
+        Style::Plain => return text.to_string(),
+        Style::Step => "1",
+        Style::Success => "32",
+        Style::Warning => "33",
+        Style::Error => "1;31",
+    };
+    if USE_COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Prints a message for humans to stderr, stdout is reserved for results
+pub fn message(style: Style, text: &str) {
+    eprintln!("{}", paint(style, text));
+}
+
+/// Prints an error with a highlighted `Error:` prefix
+pub fn error(text: &str) {
+    eprintln!("{} {}", paint(Style::Error, "Error:"), text);
+}
@@ -0,0 +1,92 @@
+use crate::result::Result;
+use clap::ValueEnum;
+use git2::{Email, EmailCreateOptions, Oid, Repository, Sort};
+use log::trace;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// How `--no-push` writes the update to `--patch-file`
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchFormat {
+    /// Mails like `git format-patch`, applied with `git am`
+    #[default]
+    Mbox,
+    /// A bundle with the target branch, fetched with `git fetch` or `git
+    /// bundle unbundle`
+    Bundle,
+}
+
+/// The commits of HEAD that are on no branch of `remote`, which a push
+/// would send, oldest first
+pub fn new_commits(repository: &Repository, remote: &str) -> Result<Vec<Oid>> {
+    let mut revwalk = repository.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    revwalk.push_head()?;
+    revwalk.hide_glob(&format!("refs/remotes/{}/*", remote))?;
+    Ok(revwalk.collect::<std::result::Result<_, _>>()?)
+}
+
+/// Writes `commits` as one mbox file, numbered like `git format-patch`
+pub fn write_mbox(
+    repository: &Repository,
+    commits: &[Oid],
+    path: &Path,
+) -> Result<()> {
+    let mut mbox = Vec::new();
+    for (index, id) in commits.iter().enumerate() {
+        let commit = repository.find_commit(*id)?;
+        let diff = repository.diff_tree_to_tree(
+            Some(&commit.parent(0)?.tree()?),
+            Some(&commit.tree()?),
+            None,
+        )?;
+        let email = Email::from_diff(
+            &diff,
+            index + 1,
+            commits.len(),
+            id,
+            commit.summary().unwrap_or_default(),
+            commit.body().unwrap_or_default(),
+            &commit.author(),
+            &mut EmailCreateOptions::new(),
+        )?;
+        mbox.extend_from_slice(email.as_slice());
+    }
+    fs::write(path, mbox)?;
+    Ok(())
+}
+
+/// Runs `git bundle create` for `reference` without the commits that are
+/// already on a branch of `remote`
+pub fn write_bundle(
+    repository: &Repository,
+    reference: &str,
+    remote: &str,
+    path: &Path,
+) -> Result<()> {
+    // git runs in the repository, so a relative path would end up there
+    let path = env::current_dir()?.join(path);
+    trace!("Writing {} to bundle {}", reference, path.display());
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repository.path())
+        .args(["bundle", "create", "--quiet"])
+        .arg(&path)
+        .arg(reference)
+        .arg("--not")
+        .arg(format!("--remotes={}", remote))
+        .output()
+        .map_err(|e| format!("Could not run git bundle: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git bundle create failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
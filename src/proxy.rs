@@ -0,0 +1,99 @@
+use crate::config;
+use crate::git_url;
+use crate::redact;
+use clap::Args;
+use git2::ProxyOptions;
+use log::trace;
+use serde::Serialize;
+use std::env;
+
+#[derive(Args, Serialize, Debug, Clone)]
+pub struct ProxyArgs {
+    /// Proxy for HTTP(S) remotes [default: $HTTPS_PROXY or $HTTP_PROXY,
+    /// hosts in $NO_PROXY are not proxied]
+    #[arg(long, env = "DEPLOYMENT_PROXY_URL", hide_env_values = true)]
+    #[serde(serialize_with = "config::optional_url")]
+    proxy_url: Option<String>,
+}
+
+/// Reads an environment variable in its upper or lower case spelling
+fn env_var(name: &str) -> Option<String> {
+    env::var(name)
+        .or_else(|_| env::var(name.to_lowercase()))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+/// Whether `host` is excluded from proxying by a `NO_PROXY` style list.
+/// Entries match the host itself and all of its subdomains, a leading dot
+/// like in `.internal.example.com` is optional.
+fn is_excluded(no_proxy: &str, host: &str) -> bool {
+    let host = host.to_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*"
+                || host == entry
+                || host.ends_with(&format!(".{}", entry))
+        })
+}
+
+impl ProxyArgs {
+    fn proxy_url(&self, url: &str) -> Option<String> {
+        let variable = if url.starts_with("https://") {
+            "HTTPS_PROXY"
+        } else if url.starts_with("http://") {
+            "HTTP_PROXY"
+        } else {
+            return None;
+        };
+        let host = git_url::host(url)?;
+        if let Some(no_proxy) = env_var("NO_PROXY") {
+            if is_excluded(&no_proxy, host) {
+                return None;
+            }
+        }
+        self.proxy_url.clone().or_else(|| env_var(variable))
+    }
+
+    pub fn proxy_options(&self, url: &str) -> ProxyOptions<'static> {
+        let mut proxy_options = ProxyOptions::new();
+        match self.proxy_url(url) {
+            Some(proxy_url) => {
+                trace!(
+                    "Using proxy {} for {}",
+                    redact::urls(&proxy_url),
+                    redact::urls(url)
+                );
+                proxy_options.url(&proxy_url);
+            }
+            None => trace!("Using no proxy for {}", redact::urls(url)),
+        }
+        proxy_options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_host_and_subdomains() {
+        let no_proxy = "localhost, .internal.example.com,git.example.org";
+        assert!(is_excluded(no_proxy, "localhost"));
+        assert!(is_excluded(no_proxy, "internal.example.com"));
+        assert!(is_excluded(no_proxy, "git.internal.example.com"));
+        assert!(is_excluded(no_proxy, "GIT.example.org"));
+        assert!(!is_excluded(no_proxy, "example.org"));
+        assert!(!is_excluded(no_proxy, "evilgit.example.org"));
+        assert!(!is_excluded(no_proxy, "github.com"));
+    }
+
+    #[test]
+    fn wildcard_excludes_everything() {
+        assert!(is_excluded("*", "github.com"));
+        assert!(!is_excluded(" , ", "github.com"));
+    }
+}
@@ -1,2 +1,176 @@
-pub type Error = Box<dyn std::error::Error>;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    /// HEAD of a repository is not on a branch where one is required
+    NotABranch(String),
+    /// HEAD of the child repository is not at the given ref
+    HeadMismatch(String),
+    /// No submodule of the composite repository contains the child commit
+    SubmoduleNotFound(String),
+    /// A ref that is neither a branch nor a tag
+    InvalidGitRef(String),
+    /// The target branch does not exist on the remote and
+    /// `--create-missing-branch` is not given
+    MissingBranch(String),
+    /// Options or config file values that cannot be used
+    InvalidArguments(String),
+    /// Authentication failed or a remote could not be reached in time
+    Transport(String),
+    /// The remote refused to update the target branch
+    PushRejected(String),
+    /// The target branch moved since it was cloned, so the update has to
+    /// be made again on top of it
+    Conflict(String),
+    /// The user declined the confirmation prompt
+    Aborted(String),
+    Git(git2::Error),
+    Io(std::io::Error),
+    /// Everything that callers do not need to tell apart
+    Other(String),
+}
+
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_SUBMODULE_NOT_FOUND: i32 = 2;
+pub const EXIT_TRANSPORT: i32 = 3;
+pub const EXIT_PUSH_REJECTED: i32 = 4;
+pub const EXIT_INVALID_ARGUMENTS: i32 = 5;
+/// `pipeline --dry-run` or `--check` found nothing to update
+pub const EXIT_UP_TO_DATE: i32 = 6;
+pub const EXIT_ABORTED: i32 = 7;
+/// `status` found a submodule that is not at the child commit
+pub const EXIT_UPDATE_PENDING: i32 = 8;
+/// A check of `validate` or `validate-auth` failed
+pub const EXIT_CHECK_FAILED: i32 = 9;
+
+pub type Error = PipelineError;
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotABranch(message)
+            | Self::SubmoduleNotFound(message)
+            | Self::InvalidArguments(message)
+            | Self::MissingBranch(message)
+            | Self::Transport(message)
+            | Self::PushRejected(message)
+            | Self::Conflict(message)
+            | Self::Aborted(message)
+            | Self::Other(message) => f.write_str(message),
+            Self::HeadMismatch(git_ref) => {
+                write!(f, "HEAD is not at {}", git_ref)
+            }
+            Self::InvalidGitRef(git_ref) => write!(
+                f,
+                "Invalid git ref {}, expected a branch or a tag like main, \
+                origin/main, refs/heads/<branch> or refs/tags/<tag>",
+                git_ref
+            ),
+            Self::Git(error) => write!(f, "{}", error),
+            Self::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl PipelineError {
+    /// Exit code of the process for this kind of failure, see
+    /// `EXIT_CODES`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::SubmoduleNotFound(_) => EXIT_SUBMODULE_NOT_FOUND,
+            Self::Transport(_) => EXIT_TRANSPORT,
+            Self::Git(error)
+                if error.code() == git2::ErrorCode::Auth
+                    || error.code() == git2::ErrorCode::Certificate
+                    || matches!(
+                        error.class(),
+                        git2::ErrorClass::Net
+                            | git2::ErrorClass::Http
+                            | git2::ErrorClass::Ssh
+                            | git2::ErrorClass::Ssl
+                    ) =>
+            {
+                EXIT_TRANSPORT
+            }
+            Self::PushRejected(_) | Self::Conflict(_) => EXIT_PUSH_REJECTED,
+            Self::Aborted(_) => EXIT_ABORTED,
+            Self::NotABranch(_)
+            | Self::HeadMismatch(_)
+            | Self::InvalidGitRef(_)
+            | Self::InvalidArguments(_)
+            | Self::MissingBranch(_) => EXIT_INVALID_ARGUMENTS,
+            Self::Git(_) | Self::Io(_) | Self::Other(_) => EXIT_OTHER,
+        }
+    }
+}
+
+/// Shown in `--help`
+pub const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Other errors
+  2  No submodule of the composite repository contains the child commit
+  3  Authentication failed or a remote could not be reached
+  4  The push was rejected
+  5  Invalid options, config file or git ref
+  6  Nothing to update with `pipeline --dry-run` or `--check`
+  7  The push was not confirmed
+  8  An update is pending with `status`
+  9  A check failed with `validate` or `validate-auth`";
+
+impl std::error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Git(error) => Some(error),
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<git2::Error> for PipelineError {
+    fn from(error: git2::Error) -> Self {
+        Self::Git(error)
+    }
+}
+
+impl From<std::io::Error> for PipelineError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<String> for PipelineError {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<&str> for PipelineError {
+    fn from(message: &str) -> Self {
+        Self::Other(message.to_string())
+    }
+}
+
+/// Errors of dependencies that are only ever shown to the user
+macro_rules! other_error {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for PipelineError {
+                fn from(error: $error) -> Self {
+                    Self::Other(error.to_string())
+                }
+            }
+        )*
+    };
+}
+
+other_error!(
+    std::string::FromUtf8Error,
+    std::time::SystemTimeError,
+    jsonwebtoken::errors::Error,
+    openssl::error::ErrorStack,
+    serde_json::Error,
+    ureq::Error,
+);
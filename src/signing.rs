@@ -0,0 +1,242 @@
+use crate::result::Result;
+use git2::{Config, ErrorCode, Oid, Repository};
+use log::trace;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use tempfile::{tempdir, NamedTempFile};
+
+/// Creates an armored detached signature of `buffer` the same way `git
+/// commit -S` does. `gpg.program` and `user.signingkey` are honored, with
+/// `gpg.format = ssh` the key is an SSH key.
+pub fn gpg_sign(
+    config: &Config,
+    key: Option<&str>,
+    buffer: &str,
+) -> Result<String> {
+    if config.get_string("gpg.format").is_ok_and(|x| x == "ssh") {
+        let key = match key {
+            Some(key) => PathBuf::from(key),
+            None => config.get_path("user.signingkey").map_err(|_| {
+                "gpg.format is ssh, but user.signingkey names no key file"
+            })?,
+        };
+        return ssh_sign(config, &key, None, buffer);
+    }
+    let program = config
+        .get_string("gpg.program")
+        .unwrap_or_else(|_| "gpg".to_string());
+    let key = match key {
+        Some(key) => Some(key.to_string()),
+        None => config.get_string("user.signingkey").ok(),
+    };
+
+    let mut command = Command::new(&program);
+    command.arg("--status-fd=2").arg("-bsa");
+    if let Some(key) = &key {
+        command.arg("-u").arg(key);
+    }
+    trace!("Signing commit with {} (key: {:?})", program, key);
+    let output = run_with_input(&mut command, &program, buffer.as_bytes())?;
+    let status = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !status.contains("[GNUPG:] SIG_CREATED ") {
+        return Err(
+            format!("{} failed to sign: {}", program, status.trim()).into()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Creates an SSH signature of `buffer` like `git commit -S` does with
+/// `gpg.format = ssh`, honoring `gpg.ssh.program`. For a public `key` the
+/// private key is taken from the ssh-agent. The passphrase is read from
+/// `passphrase_file`, otherwise ssh-keygen asks for it on the terminal.
+pub fn ssh_sign(
+    config: &Config,
+    key: &Path,
+    passphrase_file: Option<&Path>,
+    buffer: &str,
+) -> Result<String> {
+    let program = config
+        .get_string("gpg.ssh.program")
+        .unwrap_or_else(|_| "ssh-keygen".to_string());
+    // ssh-keygen writes the signature next to the signed file
+    let directory = tempdir()?;
+    let data_file = directory.path().join("commit");
+    fs::write(&data_file, buffer)?;
+    let mut signature_file = OsString::from(&data_file);
+    signature_file.push(".sig");
+
+    let mut command = Command::new(&program);
+    command
+        .args(["-Y", "sign", "-n", "git", "-f"])
+        .arg(key)
+        .arg(&data_file);
+    trace!("Signing commit with {} (key: {})", program, key.display());
+    let output = match passphrase_file {
+        Some(path) => {
+            let passphrase = fs::read(path).map_err(|e| {
+                format!("Could not read {}: {}", path.display(), e)
+            })?;
+            // read from stdin as it is no terminal
+            run_with_input(&mut command, &program, &passphrase)?
+        }
+        None => command
+            .stdin(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("Could not run {}: {}", program, e))?,
+    };
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed to sign: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(fs::read_to_string(signature_file)?)
+}
+
+/// Verifies the GPG or SSH signature of the commit `id` like `git
+/// verify-commit` does. SSH signatures are checked against `allowed_signers`
+/// or `gpg.ssh.allowedSignersFile`.
+pub fn verify_commit(
+    repository: &Repository,
+    id: Oid,
+    allowed_signers: Option<&Path>,
+) -> Result<()> {
+    let (signature, data) = match repository.extract_signature(&id, None) {
+        Ok(extracted) => extracted,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            return Err(format!("Commit {} is not signed", id).into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let signature_file = NamedTempFile::new()?;
+    fs::write(signature_file.path(), &*signature)?;
+
+    let config = repository.config()?;
+    if signature.starts_with(b"-----BEGIN SSH SIGNATURE-----") {
+        let allowed_signers = match allowed_signers {
+            Some(path) => path.to_path_buf(),
+            None => {
+                config.get_path("gpg.ssh.allowedSignersFile").map_err(|_| {
+                    format!(
+                        "Commit {} has an SSH signature, but no \
+                        --allowed-signers file is given",
+                        id
+                    )
+                })?
+            }
+        };
+        verify_ssh(id, &data, signature_file.path(), &allowed_signers)
+    } else {
+        verify_gpg(&config, id, &data, signature_file.path())
+    }
+}
+
+fn verify_gpg(
+    config: &Config,
+    id: Oid,
+    data: &[u8],
+    signature_file: &Path,
+) -> Result<()> {
+    let program = config
+        .get_string("gpg.program")
+        .unwrap_or_else(|_| "gpg".to_string());
+    trace!("Verifying the signature of {} with {}", id, program);
+    let mut command = Command::new(&program);
+    command
+        .arg("--status-fd=1")
+        .arg("--verify")
+        .arg(signature_file)
+        .arg("-");
+    let output = run_with_input(&mut command, &program, data)?;
+    let status = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !status.contains("[GNUPG:] GOODSIG ") {
+        return Err(format!(
+            "The signature of commit {} does not verify: {}",
+            id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn verify_ssh(
+    id: Oid,
+    data: &[u8],
+    signature_file: &Path,
+    allowed_signers: &Path,
+) -> Result<()> {
+    trace!(
+        "Verifying the SSH signature of {} against {}",
+        id,
+        allowed_signers.display()
+    );
+    let output = Command::new("ssh-keygen")
+        .args(["-Y", "find-principals", "-f"])
+        .arg(allowed_signers)
+        .arg("-s")
+        .arg(signature_file)
+        .output()
+        .map_err(|e| format!("Could not run ssh-keygen: {}", e))?;
+    let principals = String::from_utf8_lossy(&output.stdout);
+    let principal = match principals.lines().next() {
+        Some(principal) if output.status.success() => principal.to_string(),
+        _ => {
+            return Err(format!(
+                "Commit {} is not signed by a key in {}",
+                id,
+                allowed_signers.display()
+            )
+            .into())
+        }
+    };
+
+    let mut command = Command::new("ssh-keygen");
+    command
+        .args(["-Y", "verify", "-n", "git", "-f"])
+        .arg(allowed_signers)
+        .arg("-I")
+        .arg(&principal)
+        .arg("-s")
+        .arg(signature_file);
+    let output = run_with_input(&mut command, "ssh-keygen", data)?;
+    if !output.status.success() {
+        return Err(format!(
+            "The signature of commit {} does not verify: {}",
+            id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Runs `command` with `input` on stdin and collects its output
+fn run_with_input(
+    command: &mut Command,
+    program: &str,
+    input: &[u8],
+) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run {}: {}", program, e))?;
+    let written = child
+        .stdin
+        .take()
+        .ok_or_else(|| format!("Could not write to {}", program))?
+        .write_all(input);
+    // programs may exit before reading all input, their output tells why
+    match written {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(child.wait_with_output()?),
+    }
+}
@@ -0,0 +1,17 @@
+use clap::{Command, Parser};
+use clap_complete::Shell;
+use std::io;
+
+#[derive(Parser, Debug, Clone)]
+pub struct Args {
+    /// Shell to generate the completion script for
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+/// Prints the completion script for all subcommands and options of
+/// `command`
+pub fn run(args: Args, mut command: Command) {
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut io::stdout());
+}
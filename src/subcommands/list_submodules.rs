@@ -0,0 +1,95 @@
+use super::pipeline::{self, RepositoryWrapper};
+use crate::output;
+use crate::redact;
+use crate::result::Result;
+use clap::Parser;
+use serde::Serialize;
+
+#[derive(Parser, Serialize, Debug, Clone)]
+#[command(args_override_self = true)]
+#[group(skip)]
+pub struct Args {
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub pipeline: pipeline::Args,
+
+    /// Also check that each pinned commit is on a branch or tag of the
+    /// submodule's remote, which fetches all of them
+    #[arg(long, env = "DEPLOYMENT_CHECK_REMOTE")]
+    check_remote: bool,
+}
+
+/// A submodule of the composite repository, printed by `list-submodules`
+#[derive(Serialize, Debug)]
+pub struct SubmoduleInfo {
+    pub name: String,
+    pub path: String,
+    pub url: String,
+    /// Commit the composite repository pins the submodule to
+    pub commit: Option<String>,
+    /// Whether the pinned commit can be fetched, with `--check-remote`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_remote: Option<bool>,
+}
+
+/// Lists the submodules of the default branch of the composite repository,
+/// or of `--target-branch`
+pub fn run(mut args: Args) -> Result<Vec<SubmoduleInfo>> {
+    args.pipeline.load_files()?;
+    args.pipeline.check_single_composite()?;
+    let composite_repo =
+        RepositoryWrapper::open_composite(&args.pipeline, None)?;
+    if let Some(git_ref) = args.pipeline.target_branch_ref() {
+        composite_repo.checkout_temp_branch(&git_ref)?;
+    }
+
+    let mut submodules = vec![];
+    for mut submodule in composite_repo.submodules()? {
+        let url = composite_repo.submodule_url(&mut submodule)?;
+        let commit = submodule.head_id();
+        let on_remote = match commit {
+            Some(commit) if args.check_remote => {
+                Some(composite_repo.commit_on_remote(&url, commit)?)
+            }
+            _ => None,
+        };
+        submodules.push(SubmoduleInfo {
+            name: submodule.name().unwrap_or_default().to_string(),
+            path: submodule.path().display().to_string(),
+            url: redact::urls(&url),
+            commit: commit.map(|x| x.to_string()),
+            on_remote,
+        });
+    }
+    Ok(submodules)
+}
+
+/// One line per submodule, or all of them as JSON
+pub fn print(
+    format: output::Format,
+    submodules: &[SubmoduleInfo],
+) -> Result<()> {
+    match format {
+        output::Format::Text => {
+            for submodule in submodules {
+                let on_remote = match submodule.on_remote {
+                    Some(true) => "  on remote",
+                    Some(false) => "  missing on remote",
+                    None => "",
+                };
+                println!(
+                    "{}  {}  {}  {}{}",
+                    submodule.commit.as_deref().unwrap_or("(not pinned)"),
+                    submodule.path,
+                    submodule.name,
+                    submodule.url,
+                    on_remote
+                );
+            }
+        }
+        output::Format::Json => {
+            println!("{}", serde_json::to_string(submodules)?)
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use crate::result::{PipelineError, Result};
+use clap::{Command, Parser, ValueHint};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+pub struct Args {
+    /// Directory the man pages are written to, it has to exist
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    out_dir: PathBuf,
+}
+
+/// Writes a man page for `command` and one for every subcommand, e.g.
+/// `deploy-pipeline.1`
+pub fn run(args: Args, command: Command) -> Result<()> {
+    if !args.out_dir.is_dir() {
+        return Err(PipelineError::InvalidArguments(format!(
+            "{} is not a directory",
+            args.out_dir.display()
+        )));
+    }
+    clap_mangen::generate_to(command, &args.out_dir)?;
+    Ok(())
+}
@@ -1 +1,9 @@
+pub mod completions;
+pub mod list_submodules;
+pub mod mangen;
 pub mod pipeline;
+pub mod rollback;
+pub mod status;
+pub mod validate;
+pub mod validate_auth;
+pub mod version;
@@ -1,233 +1,4009 @@
-use crate::result::Result;
-use clap::Parser;
+use crate::auth::{self, AuthArgs};
+use crate::config;
+use crate::git_url;
+use crate::headers;
+use crate::lfs;
+use crate::output::{self, Style};
+use crate::patch::{self, PatchFormat};
+use crate::proxy::ProxyArgs;
+use crate::redact;
+use crate::result::{Error, PipelineError, Result};
+use crate::signing;
+use crate::timeout;
+use crate::tls::TlsArgs;
+#[cfg(feature = "webhook")]
+use crate::webhook;
+use clap::{Parser, ValueEnum, ValueHint};
 use git2::build::RepoBuilder;
-use git2::{BranchType, FetchOptions, Oid, Repository, Submodule};
-use log::{info, trace};
+use git2::{
+    BranchType, Commit, Config, ErrorClass, ErrorCode, FetchOptions,
+    FetchPrune, FileMode, ObjectType, Oid, Progress, PushOptions,
+    RemoteCallbacks, Repository, ResetType, Signature, Submodule,
+    SubmoduleUpdateOptions, Tree,
+};
+use log::{info, trace, warn};
+use serde::Serialize;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use tempfile::{tempdir, TempDir};
 
-#[derive(Parser, Debug, Clone)]
+/// How often the progress line is redrawn on a terminal
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often the progress is reported when stderr is not a terminal, to
+/// keep CI logs short
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The longest delay between two push attempts
+const MAX_PUSH_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Delay before the first update on top of a target branch that moved, it
+/// doubles with every further attempt up to `MAX_CONFLICT_RETRY_DELAY`
+const CONFLICT_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// The longest delay between two updates of a target branch that moved
+const MAX_CONFLICT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Set by CI systems, which never get to answer a prompt
+const CI_VARIABLES: &[&str] = &["CI", "TF_BUILD", "JENKINS_URL", "BUILDKITE"];
+
+/// Variables of GitHub Actions, GitLab CI and Azure Pipelines with the ref
+/// that is built, consulted in this order without `--git-ref`
+const CI_REF_VARIABLES: &[&str] = &[
+    "GITHUB_REF_NAME",
+    "CI_COMMIT_REF_NAME",
+    "BUILD_SOURCEBRANCH",
+];
+
+#[derive(Parser, Serialize, Debug, Clone)]
+#[command(args_override_self = true)]
 pub struct Args {
     /// Repository that is updated
-    #[arg(short, long, default_value = ".")]
-    repository: String,
+    #[arg(
+        short,
+        long,
+        default_value = ".",
+        value_hint = ValueHint::DirPath,
+        env = "DEPLOYMENT_REPOSITORY"
+    )]
+    pub(crate) repository: String,
+
+    /// File with the paths of several child repositories, one per line,
+    /// that are updated in one clone of the composite repository with a
+    /// commit each and pushed together. It replaces --repository, empty lines
+    /// and lines starting with `#` are skipped.
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["commit", "recursive", "result_file"],
+        env = "DEPLOYMENT_REPOSITORIES_FILE"
+    )]
+    pub repositories_file: Option<PathBuf>,
 
-    /// Branch to updated
-    #[arg(short, long)]
+    /// Go on with the other child repositories of --repositories-file, or
+    /// the other composite repositories, when one of them fails instead of
+    /// stopping. The run still fails after the others were pushed.
+    #[arg(long, alias = "continue-on-error", env = "DEPLOYMENT_KEEP_GOING")]
+    keep_going: bool,
+
+    /// Branch to updated [default: the ref of GITHUB_REF_NAME,
+    /// CI_COMMIT_REF_NAME or BUILD_SOURCEBRANCH, else the branch or tag HEAD
+    /// is on]. Short names like `main`, `origin/main` and `v1.0` work as
+    /// well as full refs. For a tag the default branch of the composite
+    /// repository is updated.
+    #[arg(short, long, env = "DEPLOYMENT_GIT_REF")]
     git_ref: Option<String>,
 
-    /// The composite repository
-    #[arg(short, long)]
-    composite_repository: String,
+    /// Commit of the child repository to update to instead of HEAD. HEAD
+    /// still determines the branch, but does not have to be at the commit.
+    #[arg(
+        long,
+        value_name = "SHA",
+        conflicts_with = "git_ref",
+        env = "DEPLOYMENT_COMMIT"
+    )]
+    commit: Option<String>,
+
+    /// Infer the branch of a detached HEAD that is not at a tag, as CI
+    /// systems check out, from `GITHUB_REF` or else from the only local or
+    /// remote-tracking branch at HEAD
+    #[arg(long, env = "DEPLOYMENT_ALLOW_DETACHED")]
+    allow_detached: bool,
+
+    /// Branch of the composite repository to update [default: the branch
+    /// of the child repository]
+    #[arg(
+        long,
+        value_parser = parse_branch_name,
+        env = "DEPLOYMENT_TARGET_BRANCH"
+    )]
+    target_branch: Option<String>,
+
+    /// Update another branch of the composite repository than the one of
+    /// the child repository, e.g. `main=staging`. Refs without `refs/` are
+    /// branches. A `*` matches the rest of a ref, like in
+    /// `refs/heads/release/*=refs/heads/release/*`. The first matching
+    /// mapping wins, unmapped refs keep their branch.
+    #[arg(
+        long,
+        value_name = "CHILD=COMPOSITE",
+        value_parser = parse_branch_map,
+        conflicts_with = "target_branch",
+        env = "DEPLOYMENT_BRANCH_MAP",
+        value_delimiter = '\n'
+    )]
+    #[serde(serialize_with = "config::mappings")]
+    branch_map: Vec<(String, String)>,
+
+    /// Fail if no --branch-map matches the ref of the child repository
+    #[arg(
+        long,
+        requires = "branch_map",
+        env = "DEPLOYMENT_BRANCH_MAP_REQUIRED"
+    )]
+    branch_map_required: bool,
+
+    /// The composite repository. `pipeline` accepts it several times to
+    /// update each of them in a clone of its own, one after the other.
+    /// Several ones in the environment variable are separated by newlines.
+    #[arg(
+        short = 'c',
+        long = "composite-repository",
+        id = "composite_repository",
+        value_name = "COMPOSITE_REPOSITORY",
+        required = true,
+        value_delimiter = '\n',
+        env = "DEPLOYMENT_COMPOSITE_REPOSITORY"
+    )]
+    #[serde(rename = "composite_repository", serialize_with = "config::urls")]
+    pub composite_repositories: Vec<String>,
+
+    /// URL the update is pushed to, if it differs from the one the composite
+    /// repository is cloned from
+    #[arg(long, env = "DEPLOYMENT_COMPOSITE_PUSH_URL")]
+    #[serde(serialize_with = "config::optional_url")]
+    composite_push_url: Option<String>,
+
+    /// Use the composite repository in place instead of cloning it, it must
+    /// be a local path without uncommitted changes
+    #[arg(long, env = "DEPLOYMENT_COMPOSITE_NO_CLONE")]
+    composite_no_clone: bool,
 
-    /// Set custom headers for pulling and pushing
-    #[arg(short = 'C', long)]
+    /// Keep the clone of the composite repository in this directory and
+    /// only fetch it on the next run. Runs must not share it at the same
+    /// time.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "composite_no_clone",
+        value_hint = ValueHint::DirPath,
+        env = "DEPLOYMENT_CACHE_DIR"
+    )]
+    cache_dir: Option<PathBuf>,
+
+    /// Name of the remote of the composite repository
+    #[arg(long, default_value = "origin", env = "DEPLOYMENT_REMOTE")]
+    remote: String,
+
+    /// Set custom headers for pulling and pushing. Headers written as
+    /// `<url>=<header>` are only sent to remotes whose URL starts with <url>.
+    /// Several headers in the environment variable are separated by newlines.
+    #[arg(
+        short = 'C',
+        long,
+        env = "DEPLOYMENT_CUSTOM_HEADERS",
+        value_delimiter = '\n',
+        hide_env_values = true
+    )]
+    #[serde(serialize_with = "config::headers")]
     custom_headers: Vec<String>,
+
+    /// Read additional custom headers from a file, one per line. Empty lines
+    /// and lines starting with `#` are ignored.
+    #[arg(long, env = "DEPLOYMENT_CUSTOM_HEADERS_FILE")]
+    custom_headers_file: Option<PathBuf>,
+
+    /// Headers that are only sent to the composite repository, in place of
+    /// --custom-headers. They can be scoped to URLs the same way.
+    #[arg(
+        long,
+        value_name = "HEADER",
+        env = "DEPLOYMENT_COMPOSITE_HEADER",
+        value_delimiter = '\n',
+        hide_env_values = true
+    )]
+    #[serde(serialize_with = "config::headers")]
+    composite_header: Vec<String>,
+
+    /// Headers that are only sent to the child repositories when their
+    /// submodules are fetched or pushed, in place of --custom-headers
+    #[arg(
+        long,
+        value_name = "HEADER",
+        env = "DEPLOYMENT_CHILD_HEADER",
+        value_delimiter = '\n',
+        hide_env_values = true
+    )]
+    #[serde(serialize_with = "config::headers")]
+    child_header: Vec<String>,
+
+    /// Only clone the last <DEPTH> commits of the composite repository.
+    /// Submodules are still fetched completely.
+    #[arg(long, env = "DEPLOYMENT_DEPTH")]
+    depth: Option<i32>,
+
+    /// Only clone the branch of the composite repository that is updated.
+    /// All branches are cloned for tags and branches that do not exist yet.
+    #[arg(long, env = "DEPLOYMENT_SINGLE_BRANCH")]
+    single_branch: bool,
+
+    /// Name of the local branch the update is prepared on [default:
+    /// __temporary__<pid>]
+    #[arg(long, env = "DEPLOYMENT_TEMP_BRANCH")]
+    temp_branch: Option<String>,
+
+    /// Template for the commit message. `{submodule}`, `{commit}`,
+    /// `{short_commit}`, `{tag}` and `{original_message}` are replaced by
+    /// the submodule path, the new submodule commit, its abbreviated id, the
+    /// tag of the child repository if it is deployed from one and the
+    /// commit's message. Note that `{original_message}` may span multiple
+    /// lines.
+    #[arg(long, env = "DEPLOYMENT_MESSAGE_TEMPLATE")]
+    message_template: Option<String>,
+
+    /// Git trailer appended to the commit message, e.g. `Build-Id: 42`.
+    /// The default message then separates the child commit's message with
+    /// a blank line instead of `---`, which git interpret-trailers would
+    /// take for the end of the message. Several trailers in the environment
+    /// variable are separated by newlines.
+    #[arg(
+        long,
+        value_name = "KEY: VALUE",
+        value_parser = parse_trailer,
+        env = "DEPLOYMENT_TRAILERS",
+        value_delimiter = '\n'
+    )]
+    trailer: Vec<String>,
+
+    /// Also add the trailers `Child-Commit`, `Child-Repository` with the URL
+    /// of the child repository's remote and `Deployment-Tool-Version`
+    #[arg(long, env = "DEPLOYMENT_STANDARD_TRAILERS")]
+    standard_trailers: bool,
+
+    /// Add a `Signed-off-by` trailer for the committer, or for the given
+    /// `Name <email>`. It can be repeated to sign off several people.
+    #[arg(
+        long,
+        value_name = "NAME <EMAIL>",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        value_parser = parse_sign_off,
+        env = "DEPLOYMENT_SIGN_OFF",
+        value_delimiter = '\n'
+    )]
+    sign_off: Vec<String>,
+
+    /// Name of the author of the update commit [default: author of the
+    /// child commit]
+    #[arg(long, requires = "author_email", env = "DEPLOYMENT_AUTHOR_NAME")]
+    author_name: Option<String>,
+
+    /// Email of the author of the update commit
+    #[arg(long, requires = "author_name", env = "DEPLOYMENT_AUTHOR_EMAIL")]
+    author_email: Option<String>,
+
+    /// Name of the committer of the update commit [default: committer of the
+    /// child commit]
+    #[arg(
+        long,
+        requires = "committer_email",
+        env = "DEPLOYMENT_COMMITTER_NAME"
+    )]
+    committer_name: Option<String>,
+
+    /// Email of the committer of the update commit
+    #[arg(
+        long,
+        requires = "committer_name",
+        env = "DEPLOYMENT_COMMITTER_EMAIL"
+    )]
+    committer_email: Option<String>,
+
+    /// Keep the author and committer dates of the child commit with
+    /// --author-name or --committer-name, which otherwise date the update
+    /// commit to now. Without them the child's signatures are reused as they
+    /// are.
+    #[arg(long, env = "DEPLOYMENT_PRESERVE_TIMESTAMPS")]
+    preserve_timestamps: bool,
+
+    /// GPG-sign the update commit, optionally with the given key [default
+    /// key: user.signingkey]. An empty environment variable signs with the
+    /// default key.
+    #[arg(long, value_name = "KEYID", env = "DEPLOYMENT_GPG_SIGN")]
+    #[serde(serialize_with = "config::optional_value")]
+    gpg_sign: Option<Option<String>>,
+
+    /// Sign the update commit with this SSH key like git does with
+    /// `gpg.format = ssh`. For a public key the private key is taken from
+    /// the ssh-agent. The passphrase is read from --ssh-key-passphrase-file,
+    /// or asked for on the terminal.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "gpg_sign",
+        value_hint = ValueHint::FilePath,
+        env = "DEPLOYMENT_SIGN_WITH_SSH_KEY"
+    )]
+    sign_with_ssh_key: Option<PathBuf>,
+
+    /// Refuse to update to a child commit without a valid GPG or SSH
+    /// signature
+    #[arg(long, env = "DEPLOYMENT_REQUIRE_SIGNED_HEAD")]
+    require_signed_head: bool,
+
+    /// Allowed signers file for verifying SSH signatures with
+    /// --require-signed-head [default: gpg.ssh.allowedSignersFile]
+    #[arg(
+        long,
+        requires = "require_signed_head",
+        env = "DEPLOYMENT_ALLOWED_SIGNERS"
+    )]
+    allowed_signers: Option<PathBuf>,
+
+    /// Create the target branch if it does not exist yet, from the given
+    /// branch, tag or commit [default base: the default branch of the
+    /// composite repository]. Without it a missing branch is an error. An
+    /// empty environment variable creates it from the default base.
+    #[arg(
+        long,
+        value_name = "BASE",
+        require_equals = true,
+        alias = "create-branch",
+        env = "DEPLOYMENT_CREATE_MISSING_BRANCH"
+    )]
+    #[serde(serialize_with = "config::optional_value")]
+    create_missing_branch: Option<Option<String>>,
+
+    /// Search the submodules that are already checked out, for example by
+    /// `git clone --recurse-submodules`, before fetching them. They are only
+    /// fetched if none of them contains the child commit yet.
+    #[arg(long, env = "DEPLOYMENT_NO_SUBMODULE_UPDATE")]
+    no_submodule_update: bool,
+
+    /// Check out updated submodules that use Git LFS and run `git lfs pull`
+    /// in them. This requires the `git-lfs` binary, which authenticates with
+    /// the credential helpers of git instead of the options given here.
+    #[arg(long, env = "DEPLOYMENT_LFS")]
+    lfs: bool,
+
+    /// Number of submodules that are fetched in parallel while searching for
+    /// the child commit
+    #[arg(
+        long,
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(1..),
+        env = "DEPLOYMENT_JOBS"
+    )]
+    jobs: u32,
+
+    /// Push without asking. Runs on a terminal outside of CI ask for
+    /// confirmation before anything is pushed.
+    #[arg(short, long, env = "DEPLOYMENT_YES")]
+    yes: bool,
+
+    /// Overwrite the target branch even if it cannot be fast-forwarded.
+    /// Commits on it that are not part of the update are lost. On a
+    /// terminal it requires --yes.
+    #[arg(long, env = "DEPLOYMENT_FORCE")]
+    force: bool,
+
+    /// Fetch the target branch again and redo the update on top of it if it
+    /// moved since it was cloned, up to this many times and at most 20. The
+    /// attempts are a few hundred milliseconds apart.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u32).range(..=20),
+        conflicts_with_all = ["recursive", "repositories_file"],
+        env = "DEPLOYMENT_RETRY_ON_CONFLICT"
+    )]
+    retry_on_conflict: u32,
+
+    /// Only push if the target branch of the composite repository is at
+    /// this commit. The branch is looked up again right before the push,
+    /// which fails if it moved since it was cloned either way.
+    #[arg(
+        long,
+        value_name = "COMMIT",
+        value_parser = parse_commit_id,
+        env = "DEPLOYMENT_EXPECT_REMOTE"
+    )]
+    expect_remote: Option<String>,
+
+    /// Path of the submodule to update. Only this submodule is fetched
+    /// instead of searching all submodules for the child HEAD.
+    #[arg(
+        long,
+        conflicts_with = "recursive",
+        env = "DEPLOYMENT_SUBMODULE_PATH"
+    )]
+    pub(crate) submodule_path: Option<String>,
+
+    /// How the submodule to update is found without --submodule-path:
+    /// `commit` searches all submodules for the child commit, `url` takes
+    /// the one whose URL is the one of the child repository's remote
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with = "recursive",
+        env = "DEPLOYMENT_MATCH_BY"
+    )]
+    match_by: MatchBy,
+
+    /// With `--match-by url`, update every submodule with the URL of the
+    /// child repository instead of failing if there are several. Matching
+    /// by commit always updates all submodules that contain it.
+    #[arg(
+        long,
+        conflicts_with = "submodule_path",
+        env = "DEPLOYMENT_ALL_MATCHING"
+    )]
+    all_matching: bool,
+
+    /// Commit even if the submodule already points to the child HEAD
+    #[arg(long, env = "DEPLOYMENT_ALLOW_EMPTY")]
+    allow_empty: bool,
+
+    /// Update all submodules containing the child HEAD in one commit instead
+    /// of one commit per submodule
+    #[arg(long, env = "DEPLOYMENT_COMBINE_COMMITS")]
+    combine_commits: bool,
+
+    /// Also search nested submodules. Intermediate submodules get a commit
+    /// pointing to the updated submodule, which is pushed to the same branch
+    /// of their own remote.
+    #[arg(long, env = "DEPLOYMENT_RECURSIVE")]
+    recursive: bool,
+
+    /// Seconds to wait for a connection to a remote to be established
+    #[arg(long, value_name = "SECONDS", env = "DEPLOYMENT_CONNECT_TIMEOUT")]
+    connect_timeout: Option<u64>,
+
+    /// Seconds a single clone, fetch or push may take. Connections that stall
+    /// for this long are aborted as well.
+    #[arg(long, value_name = "SECONDS", env = "DEPLOYMENT_NETWORK_TIMEOUT")]
+    network_timeout: Option<u64>,
+
+    /// Seconds all network operations together may take: cloning the
+    /// composite repository, fetching submodules and pushing, including push
+    /// retries. Local git operations are not limited.
+    #[arg(long, value_name = "SECONDS", env = "DEPLOYMENT_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Retry the push this many times if it fails due to network errors, at
+    /// most 20
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u32).range(..=20),
+        env = "DEPLOYMENT_PUSH_RETRIES"
+    )]
+    push_retries: u32,
+
+    /// Delay before the first push retry in milliseconds, it doubles with
+    /// every further retry up to a minute
+    #[arg(long, default_value_t = 1000, env = "DEPLOYMENT_PUSH_RETRY_DELAY")]
+    push_retry_delay: u64,
+
+    /// Do not report the progress of clones and fetches. It is shown as an
+    /// updating line on a terminal and every few seconds otherwise.
+    #[arg(long, env = "DEPLOYMENT_NO_PROGRESS")]
+    no_progress: bool,
+
+    /// The progress is reported by default, kept for compatibility. It has
+    /// no environment variable as it does not change anything.
+    #[arg(long, hide = true, overrides_with = "no_progress")]
+    #[serde(skip)]
+    progress: bool,
+
+    /// Create the update commit and print what would be pushed instead of
+    /// pushing it. Exits with 6 if there is nothing to update.
+    #[arg(long, env = "DEPLOYMENT_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Exit with 6 if the submodule already points to the child commit, like
+    /// `--dry-run` but also when pushing, so retriggered runs can be told
+    /// apart from updates
+    #[arg(long, env = "DEPLOYMENT_CHECK")]
+    pub check: bool,
+
+    /// Commit the update without pushing it and write it to --patch-file
+    /// instead, to carry it to a composite repository that cannot be
+    /// reached from here
+    #[arg(
+        long,
+        requires = "patch_file",
+        conflicts_with_all = ["dry_run", "recursive"],
+        env = "DEPLOYMENT_NO_PUSH"
+    )]
+    no_push: bool,
+
+    /// File the update commits are written to with --no-push
+    #[arg(
+        long,
+        requires = "no_push",
+        value_hint = ValueHint::FilePath,
+        env = "DEPLOYMENT_PATCH_FILE"
+    )]
+    patch_file: Option<PathBuf>,
+
+    /// Format of --patch-file: `mbox` for `git am`, or a `bundle` with the
+    /// target branch for `git fetch`
+    #[arg(long, value_enum, default_value_t, env = "DEPLOYMENT_PATCH_FORMAT")]
+    patch_format: PatchFormat,
+
+    /// Write the resulting commit of the composite repository to this file
+    /// after a successful run
+    #[arg(long, value_name = "PATH", env = "DEPLOYMENT_OUTPUT_COMMIT_FILE")]
+    output_commit_file: Option<PathBuf>,
+
+    /// Write `key=value` lines with the composite commit, submodule path,
+    /// child commit, branch and whether anything was updated to this file
+    /// after a successful run. It is replaced atomically and not created if
+    /// the run fails.
+    #[arg(long, value_name = "PATH", env = "DEPLOYMENT_RESULT_FILE")]
+    result_file: Option<PathBuf>,
+
+    /// POST a JSON summary of the update to this URL after pushing, e.g. an
+    /// incoming webhook of Slack or Microsoft Teams. A failure is only
+    /// warned about.
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "URL",
+        env = "DEPLOYMENT_WEBHOOK_URL",
+        hide_env_values = true
+    )]
+    #[serde(serialize_with = "config::secret")]
+    webhook_url: Option<String>,
+
+    /// Fail if the webhook cannot be notified, the update is pushed already
+    /// at that point
+    #[cfg(feature = "webhook")]
+    #[arg(long, requires = "webhook_url", env = "DEPLOYMENT_WEBHOOK_REQUIRED")]
+    webhook_required: bool,
+
+    /// Append `composite_commit=<sha>` to $GITHUB_OUTPUT after a successful
+    /// run
+    #[arg(long, env = "DEPLOYMENT_GITHUB_OUTPUT")]
+    github_output: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    auth: AuthArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    proxy: ProxyArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    /// Set from the global `--output`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub output: output::Format,
+
+    /// Set from the global `--quiet`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub quiet: bool,
+
+    /// Set from the global `--strict`
+    #[arg(skip)]
+    #[serde(skip)]
+    pub strict: bool,
 }
 
-impl Args {
-    fn custom_headers_ref(&self) -> Vec<&str> {
-        self.custom_headers
-            .iter()
-            .map(|x| x.as_str())
-            .collect::<Vec<&str>>()
-    }
+/// How the submodule that embeds the child repository is found
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchBy {
+    /// The submodules that contain the child commit
+    #[default]
+    Commit,
+    /// The submodule with the URL of the child repository's remote
+    Url,
 }
 
-struct RepositoryWrapper<'a> {
-    repository: Repository,
-    args: &'a Args,
-    #[allow(dead_code)]
-    tempdir: Option<TempDir>,
+/// Whether a remote belongs to the composite repository or to a child
+/// repository, which are fetched as submodules. They can have headers of
+/// their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RemoteKind {
+    Composite,
+    Child,
 }
 
-impl<'a> RepositoryWrapper<'a> {
-    pub fn clone(url: &str, args: &'a Args) -> Result<Self> {
-        let mut fetch_options = FetchOptions::new();
-        fetch_options.custom_headers(&args.custom_headers_ref());
+/// The result of a pipeline run, printed with `--output json`
+#[derive(Serialize, Debug)]
+pub struct PipelineOutcome {
+    /// Working directory of the child repository
+    pub child_repository: String,
+    /// Ref of the child repository, `refs/heads/<branch>` or
+    /// `refs/tags/<tag>`
+    pub git_ref: String,
+    pub composite_repository: String,
+    /// Paths of the updated submodules, separated by `, `
+    pub submodule_path: Option<String>,
+    /// Every updated submodule with the commits it pointed to before and
+    /// after the run
+    pub submodules: Vec<SubmoduleUpdate>,
+    /// Commit the submodule pointed to before the run
+    pub previous_commit: Option<String>,
+    /// Child commit the submodule points to now
+    pub new_commit: String,
+    /// HEAD of the composite repository after the run
+    pub composite_commit: String,
+    pub target_branch: String,
+    /// Whether an update commit was created
+    pub updated: bool,
+    pub pushed: bool,
+}
 
-        let tempdir = tempdir()?;
-        trace!("Cloning {} into {}", url, tempdir.path().display());
-        let repository = RepoBuilder::new()
-            .fetch_options(fetch_options)
-            .clone(url, tempdir.path())?;
+/// One submodule changed by a pipeline run
+#[derive(Serialize, Debug)]
+pub struct SubmoduleUpdate {
+    pub path: String,
+    pub previous_commit: String,
+    pub new_commit: String,
+}
 
-        Ok(Self {
-            repository,
-            args,
-            tempdir: Some(tempdir),
+/// The result of a run with `--repositories-file`, printed with `--output
+/// json`
+#[derive(Serialize, Debug)]
+pub struct BatchOutcome {
+    pub children: Vec<ChildOutcome>,
+    pub composite_repository: String,
+    /// HEAD of the composite repository after the run
+    pub composite_commit: String,
+    pub target_branch: String,
+    /// Whether any update commit was created
+    pub updated: bool,
+    pub pushed: bool,
+}
+
+impl BatchOutcome {
+    /// The first child repository that failed
+    pub fn failure(&self) -> Option<(&str, i32)> {
+        self.children.iter().find_map(|x| match x {
+            ChildOutcome::Failed {
+                child_repository,
+                exit_code,
+                ..
+            } => Some((child_repository.as_str(), *exit_code)),
+            ChildOutcome::Updated(_) => None,
         })
     }
+}
 
-    fn git_ref(&self) -> Result<String> {
-        let head = self.repository.head()?;
-        if let Some(git_ref) = &self.args.git_ref {
-            Ok(git_ref.to_string())
-        } else if head.is_branch() {
-            Ok(self.repository.head()?.name().unwrap().to_string())
-        } else {
-            Err("No branch name given and HEAD is not a branch".into())
+/// The result of a run with several `--composite-repository`, printed with
+/// `--output json`
+#[derive(Serialize, Debug)]
+pub struct CompositesOutcome {
+    pub composites: Vec<CompositeOutcome>,
+    /// Whether any composite repository got an update commit
+    pub updated: bool,
+}
+
+impl CompositesOutcome {
+    /// The first composite repository that failed
+    pub fn failure(&self) -> Option<(&str, i32)> {
+        self.composites.iter().find_map(|x| match x {
+            CompositeOutcome::Failed {
+                composite_repository,
+                exit_code,
+                ..
+            } => Some((composite_repository.as_str(), *exit_code)),
+            CompositeOutcome::Updated(_) => None,
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum CompositeOutcome {
+    Updated(PipelineOutcome),
+    Failed {
+        composite_repository: String,
+        error: String,
+        /// Exit code the error would have on its own
+        #[serde(skip)]
+        exit_code: i32,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum ChildOutcome {
+    Updated(PipelineOutcome),
+    Failed {
+        child_repository: String,
+        error: String,
+        /// Exit code the error would have on its own
+        #[serde(skip)]
+        exit_code: i32,
+    },
+}
+
+impl Args {
+    /// Merges the headers from `--custom-headers-file` into the ones given
+    /// on the command line and reads `--token-file`. The tokens are only
+    /// sent to the hosts of the composite repositories from then on.
+    pub(crate) fn load_files(&mut self) -> Result<()> {
+        if let Some(path) = &self.custom_headers_file {
+            let headers = headers::read_file(path)?;
+            self.custom_headers.extend(headers);
+        }
+        let mut urls: Vec<String> = self
+            .composite_repositories
+            .iter()
+            .chain(&self.composite_push_url)
+            .cloned()
+            .collect();
+        // a local composite repository is fetched from its remote
+        for path in &self.composite_repositories {
+            let Ok(repository) = Repository::open(path) else {
+                continue;
+            };
+            let Ok(remote) = repository.find_remote(&self.remote) else {
+                continue;
+            };
+            urls.extend(remote.url().map(str::to_string));
+            urls.extend(remote.pushurl().map(str::to_string));
+        }
+        for url in &urls {
+            self.auth.allow_token_for(url);
         }
+        self.auth.load_token_file()
     }
 
-    fn head_id(&self) -> Result<Oid> {
-        let git_ref = self.git_ref()?;
-        let reference = self.repository.find_reference(&git_ref)?;
-        let commit = reference.peel_to_commit()?;
-        if self.repository.head()?.peel_to_commit()?.id() == commit.id() {
-            Ok(commit.id())
-        } else {
-            Err("HEAD is not on the given branch".into())
+    /// Fails before anything is changed if the outputs could not be written
+    /// after the run
+    fn check_outputs(&self) -> Result<()> {
+        let files = [
+            ("--output-commit-file", &self.output_commit_file),
+            ("--result-file", &self.result_file),
+        ];
+        for (option, path) in files {
+            let Some(path) = path else { continue };
+            let parent = path.parent().filter(|x| !x.as_os_str().is_empty());
+            if parent.is_some_and(|x| !x.is_dir()) {
+                return Err(PipelineError::InvalidArguments(format!(
+                    "The directory of {} {} does not exist",
+                    option,
+                    path.display()
+                )));
+            }
+        }
+        if self.github_output && env::var_os("GITHUB_OUTPUT").is_none() {
+            return Err(PipelineError::InvalidArguments(
+                "--github-output requires $GITHUB_OUTPUT to be set".to_string(),
+            ));
         }
+        Ok(())
     }
 
-    pub fn open(path: &str, args: &'a Args) -> Result<Self> {
-        let repository = Repository::open(path)?;
-        Ok(Self {
-            repository,
-            args,
-            tempdir: None,
-        })
+    /// Writes the resulting composite commit for later steps of a pipeline
+    fn write_outputs(&self, outcome: &PipelineOutcome) -> Result<()> {
+        if let Some(path) = &self.result_file {
+            write_result_file(path, outcome)?;
+        }
+        self.write_composite_commit(&outcome.composite_commit)
     }
 
-    /// branch_name is the full branch name containing the remote name (i.e. `refs/heads/main`)
-    pub fn checkout_temp_branch(&self, git_ref: &str) -> Result<()> {
-        trace!(
-            "Checking out {} in {}",
-            git_ref,
-            self.repository.path().display()
-        );
-        let branch_name = Self::get_branch_name_from_ref(git_ref)?;
-        let branch = self.repository.find_branch(
-            &format!("origin/{}", branch_name),
-            BranchType::Remote,
-        );
-        let commit = match branch {
-            Ok(branch) => {
-                trace!("Found reference {}.", git_ref);
-                branch.get().peel_to_commit()?
-            }
-            Err(e) => {
-                trace!("Did not find reference {}, using head: {}", git_ref, e);
-                self.repository.head()?.peel_to_commit()?
+    /// The outputs that only carry the composite commit
+    fn write_composite_commit(&self, composite_commit: &str) -> Result<()> {
+        if let Some(path) = &self.output_commit_file {
+            fs::write(path, format!("{}\n", composite_commit))?;
+        }
+        if self.github_output {
+            if let Some(path) = env::var_os("GITHUB_OUTPUT") {
+                let mut file =
+                    OpenOptions::new().append(true).create(true).open(path)?;
+                writeln!(file, "composite_commit={}", composite_commit)?;
             }
+        }
+        Ok(())
+    }
+
+    /// The pid suffix keeps concurrent runs on the same clone apart
+    fn temp_branch(&self) -> String {
+        match &self.temp_branch {
+            Some(temp_branch) => temp_branch.clone(),
+            None => format!("__temporary__{}", std::process::id()),
+        }
+    }
+
+    /// Prints progress messages for humans to stderr, they are only logged
+    /// with `--quiet`. stdout is reserved for the result.
+    pub(crate) fn print(&self, message: &str) {
+        self.print_styled(Style::Plain, message);
+    }
+
+    /// Like `print`, highlighted by `style` if stderr is colored
+    pub(crate) fn print_styled(&self, style: Style, message: &str) {
+        if self.quiet {
+            info!("{}", message);
+        } else {
+            output::message(style, message);
+        }
+    }
+
+    /// The custom headers for `kind`, --custom-headers unless headers were
+    /// given for it alone
+    fn headers_for(&self, kind: RemoteKind) -> &[String] {
+        let headers = match kind {
+            RemoteKind::Composite => &self.composite_header,
+            RemoteKind::Child => &self.child_header,
+        };
+        if headers.is_empty() {
+            &self.custom_headers
+        } else {
+            headers
+        }
+    }
+
+    /// Notifies `--webhook-url` of a pushed update. Failures only warn
+    /// unless `--webhook-required` is given.
+    #[cfg(feature = "webhook")]
+    fn notify_webhook(&self, outcome: &PipelineOutcome) -> Result<()> {
+        let Some(url) = &self.webhook_url else {
+            return Ok(());
         };
+        match webhook::notify(url, outcome) {
+            Err(e) if !self.webhook_required => {
+                self.print_styled(Style::Warning, &format!("Warning: {}", e));
+                Ok(())
+            }
+            result => result,
+        }
+    }
 
-        trace!(
-            "Creating __temporary__ branch in {:?}",
-            self.repository.path()
-        );
-        self.repository.branch("__temporary__", &commit, true)?;
-        self.repository.set_head("refs/heads/__temporary__")?;
-        self.repository.checkout_head(None)?;
+    #[cfg(not(feature = "webhook"))]
+    fn notify_webhook(&self, _outcome: &PipelineOutcome) -> Result<()> {
         Ok(())
     }
 
-    fn find_submodule_by_id(&self, id: Oid) -> Result<Submodule<'_>> {
-        let submodules = self.repository.submodules()?;
-        let (_repository, submodule) = submodules
-            .into_iter()
-            .map(|mut x| {
-                x.update(true, None).unwrap();
-                (x.open().unwrap(), x)
-            })
-            .inspect(|(_, x)| trace!("Found submodule {}", x.name().unwrap(),))
-            .find(|(repository, _)| repository.find_commit(id).is_ok())
-            .ok_or("No submodule found")?;
-        info!("Found submodule: {:?}", submodule.path());
-        Ok(submodule)
+    /// The composite repository, the only one outside of the runs of
+    /// several ones
+    pub(crate) fn composite_repository(&self) -> &str {
+        self.composite_repositories
+            .first()
+            .map_or("", |x| x.as_str())
     }
 
-    fn update_submodule_to_id(
-        &self,
-        submodule: &mut Submodule,
-        id: Oid,
-    ) -> Result<()> {
-        let sub_repository = submodule.open()?;
-        let commit = sub_repository.find_commit(id)?;
-        info!("Found commit: {:?}", commit);
-        sub_repository.set_head_detached(commit.id())?;
-        submodule.add_to_index(true)?;
-        info!("Updated {:?}", sub_repository.path());
-        self.commit(submodule)?;
+    /// Fails for the subcommands and options that update a single
+    /// composite repository if several are given
+    pub(crate) fn check_single_composite(&self) -> Result<()> {
+        if self.composite_repositories.len() > 1 {
+            return Err(PipelineError::InvalidArguments(
+                "Only pipeline accepts several --composite-repository"
+                    .to_string(),
+            ));
+        }
         Ok(())
     }
 
-    fn commit(&self, submodule: &Submodule) -> Result<()> {
-        let mut index = self.repository.index()?;
-        let tree_id = index.write_tree()?;
-        let tree = self.repository.find_tree(tree_id)?;
-        let head = self.repository.head()?.peel_to_commit()?;
-        let commit = self.repository.find_commit(head.id())?;
-        let submodule_repo = submodule.open()?;
-        let submodule_commit = submodule_repo.head()?.peel_to_commit()?;
-        let message = format!(
-            "Update submodule {} to {}\n---\n{}",
-            submodule.path().display(),
-            submodule_commit.id(),
-            submodule_commit.message().unwrap()
-        );
-        self.repository.commit(
-            Some("HEAD"),
-            &submodule_commit.author(),
-            &submodule_commit.committer(),
-            &message,
-            &tree,
-            &[&commit],
-        )?;
+    /// Options of a single update that would be shared by all composite
+    /// repositories
+    fn check_composites(&self) -> Result<()> {
+        if self.composite_repositories.len() < 2 {
+            return Ok(());
+        }
+        let single = [
+            ("--repositories-file", self.repositories_file.is_some()),
+            ("--composite-push-url", self.composite_push_url.is_some()),
+            ("--expect-remote", self.expect_remote.is_some()),
+            ("--output-commit-file", self.output_commit_file.is_some()),
+            ("--result-file", self.result_file.is_some()),
+        ];
+        match single.iter().find(|(_, given)| *given) {
+            Some((option, _)) => Err(PipelineError::InvalidArguments(format!(
+                "{} cannot be used with several --composite-repository",
+                option
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Force pushes are not confirmed interactively, they have to be
+    /// acknowledged with --yes up front
+    pub(crate) fn check_force(&self) -> Result<()> {
+        if self.force && !self.yes && !self.dry_run && is_interactive() {
+            return Err(PipelineError::InvalidArguments(
+                "--force requires --yes on a terminal".to_string(),
+            ));
+        }
         Ok(())
     }
 
-    fn get_branch_name_from_ref(git_ref: &str) -> Result<&str> {
-        let prefix = "refs/heads/";
-        if let Some(branch_name) = git_ref.strip_prefix(prefix) {
-            Ok(branch_name)
-        } else {
-            Err("Invalid git ref".into())
+    /// `--target-branch` as a ref
+    pub(crate) fn target_branch_ref(&self) -> Option<String> {
+        let target_branch = self.target_branch.as_ref()?;
+        Some(format!("refs/heads/{}", target_branch))
+    }
+
+    /// The branch of the composite repository that is cloned alone with
+    /// `--single-branch`. Tags are updated on the default branch, which is
+    /// unknown before the clone.
+    pub(crate) fn clone_branch(
+        &self,
+        child_ref: &str,
+    ) -> Result<Option<String>> {
+        let mapped = self.map_ref(child_ref)?;
+        if !self.single_branch {
+            return Ok(None);
         }
+        let git_ref = mapped.as_deref().unwrap_or(child_ref);
+        Ok(self.target_branch.clone().or_else(|| {
+            git_ref.strip_prefix("refs/heads/").map(|x| x.to_string())
+        }))
     }
 
-    fn push(&self, git_ref_target: &str) -> Result<()> {
-        let branch_name = Self::get_branch_name_from_ref(git_ref_target)?;
-        let mut remote = self.repository.find_remote("origin")?;
-        let mut options = git2::PushOptions::new();
-        options.custom_headers(&self.args.custom_headers_ref());
+    /// The ref of the composite repository that `--branch-map` maps
+    /// `child_ref` to
+    fn map_ref(&self, child_ref: &str) -> Result<Option<String>> {
+        let mapped = self.branch_map.iter().find_map(|(child, composite)| {
+            match child.split_once('*') {
+                Some((prefix, suffix)) => child_ref
+                    .strip_prefix(prefix)
+                    .and_then(|x| x.strip_suffix(suffix))
+                    .map(|matched| composite.replacen('*', matched, 1)),
+                None => (child == child_ref).then(|| composite.clone()),
+            }
+        });
+        if mapped.is_none() && self.branch_map_required {
+            return Err(PipelineError::InvalidArguments(format!(
+                "No --branch-map matches {}",
+                child_ref
+            )));
+        }
+        Ok(mapped)
+    }
 
-        // https://docs.rs/git2/latest/git2/struct.RemoteCallbacks.html
-        // git -c http.https://<url of submodule repository>.extraheader="AUTHORIZATION: basic <BASE64_ENCODED_TOKEN_DESCRIBED_ABOVE>" submodule update --init --recursive
-        // https://learn.microsoft.com/en-us/azure/devops/pipelines/repos/pipeline-options-for-git?view=azure-devops&tabs=yaml#alternative-to-using-the-checkout-submodules-option
-        let head = self.repository.head()?;
-        if !head.is_branch() {
-            return Err("Composite repository is not on a branch".into());
+    /// Warns about a fallback that may not be what was intended, it fails
+    /// the run with `--strict`. Like `print`, the warning is only logged
+    /// with `--quiet`.
+    fn fallback(&self, message: &str) -> Result<()> {
+        if self.strict {
+            return Err(PipelineError::Other(format!(
+                "{} (--strict)",
+                message
+            )));
         }
+        if self.quiet {
+            warn!("{}", message);
+        } else {
+            output::message(Style::Warning, &format!("Warning: {}", message));
+        }
+        Ok(())
+    }
 
-        println!("Pushing to {}", branch_name);
-        remote.push(
-            &[format!(
-                "refs/heads/__temporary__:refs/heads/{}",
-                branch_name
-            )],
-            Some(&mut options),
-        )?;
+    /// All headers that are sent to `url`
+    fn custom_headers(&self, url: &str, kind: RemoteKind) -> Vec<String> {
+        let mut headers: Vec<String> =
+            headers::for_url(self.headers_for(kind), url)
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect();
+        headers.extend(self.auth.token_headers(url));
+        headers
+    }
+
+    /// All headers that are sent to `url` when pushing
+    fn push_custom_headers(&self, url: &str, kind: RemoteKind) -> Vec<String> {
+        let mut headers: Vec<String> =
+            headers::for_url(self.headers_for(kind), url)
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect();
+        headers.extend(self.auth.push_token_headers(url));
+        headers
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it, so readers
+/// never see a partial result
+fn write_result_file(path: &Path, outcome: &PipelineOutcome) -> Result<()> {
+    let content = format!(
+        "composite_commit={}\nsubmodule_path={}\nchild_commit={}\n\
+        branch={}\nupdated={}\n",
+        outcome.composite_commit,
+        outcome.submodule_path.as_deref().unwrap_or_default(),
+        outcome.new_commit,
+        outcome.target_branch,
+        outcome.updated
+    );
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })?;
+    Ok(())
+}
+
+/// Whether someone can answer a prompt, which is never the case in CI
+fn is_interactive() -> bool {
+    let is_ci = CI_VARIABLES.iter().any(|x| env::var_os(x).is_some());
+    io::stdin().is_terminal() && !is_ci
+}
+
+/// Asks on the terminal before anything is pushed
+fn confirm_push(pushes: &[String]) -> Result<()> {
+    output::message(Style::Step, "About to push:");
+    for push in pushes {
+        output::message(Style::Plain, &format!("  {}", push));
+    }
+    eprint!("{} ", output::paint(Style::Step, "Continue? [y/N]"));
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
         Ok(())
+    } else {
+        Err(PipelineError::Aborted(
+            "Aborted, nothing was pushed".to_string(),
+        ))
     }
 }
 
-pub fn run(args: Args) -> Result<()> {
-    let child_repository = RepositoryWrapper::open(&args.repository, &args)?;
+/// Accepts `<branch>` or `refs/heads/<branch>` if it is a valid branch name
+fn parse_branch_name(value: &str) -> std::result::Result<String, String> {
+    let name = value.strip_prefix("refs/heads/").unwrap_or(value);
+    if git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+        Ok(name.to_string())
+    } else {
+        Err(format!("{:?} is not a valid branch name", value))
+    }
+}
 
-    let git_ref = child_repository.git_ref()?;
-    let child_head_oid = child_repository.head_id()?;
+/// Accepts `<child ref>=<composite branch>` for `--branch-map`, both sides
+/// may contain one `*`
+fn parse_branch_map(
+    value: &str,
+) -> std::result::Result<(String, String), String> {
+    let (child, composite) = value
+        .split_once('=')
+        .filter(|(child, composite)| !child.is_empty() && !composite.is_empty())
+        .ok_or("expected <child ref>=<composite branch>")?;
+    let full_ref = |x: &str| {
+        if x.starts_with("refs/") {
+            x.to_string()
+        } else {
+            format!("refs/heads/{}", x)
+        }
+    };
+    let (child, composite) = (full_ref(child), full_ref(composite));
+    if !composite.starts_with("refs/heads/") {
+        return Err(format!("{} is not a branch", composite));
+    }
+    let wildcards = |x: &str| x.matches('*').count();
+    match (wildcards(&child), wildcards(&composite)) {
+        (0, 0) | (1, 1) | (1, 0) => Ok((child, composite)),
+        _ => Err("expected at most one * on each side, and none on the \
+            right without one on the left"
+            .to_string()),
+    }
+}
 
-    let composite_repo =
-        RepositoryWrapper::clone(&args.composite_repository, &args)?;
+/// Accepts a full or abbreviated commit id
+fn parse_commit_id(value: &str) -> std::result::Result<String, String> {
+    if (4..=40).contains(&value.len())
+        && value.chars().all(|x| x.is_ascii_hexdigit())
+    {
+        Ok(value.to_ascii_lowercase())
+    } else {
+        Err(format!("{:?} is not a commit id", value))
+    }
+}
 
-    composite_repo.checkout_temp_branch(&git_ref)?;
+/// Accepts `Key: value` for `--trailer`, the key being letters, digits and
+/// `-` like the trailers git knows
+fn parse_trailer(value: &str) -> std::result::Result<String, String> {
+    let invalid = || format!("{:?} is not a trailer like `Key: value`", value);
+    let (key, trailer_value) = value.split_once(':').ok_or_else(invalid)?;
+    let (key, trailer_value) = (key.trim(), trailer_value.trim());
+    if key.is_empty()
+        || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        || trailer_value.is_empty()
+        || trailer_value.contains('\n')
+    {
+        return Err(invalid());
+    }
+    Ok(format!("{}: {}", key, trailer_value))
+}
+
+/// Accepts `Name <email>` for `--sign-off`, or nothing for the committer
+fn parse_sign_off(value: &str) -> std::result::Result<String, String> {
+    if value.is_empty() {
+        return Ok(String::new());
+    }
+    let invalid = || format!("{:?} is not like `Name <email>`", value);
+    let (name, email) = value
+        .trim()
+        .strip_suffix('>')
+        .and_then(|x| x.split_once('<'))
+        .ok_or_else(invalid)?;
+    if name.trim().is_empty() || !email.contains('@') || email.contains('>') {
+        return Err(invalid());
+    }
+    Ok(format!("{} <{}>", name.trim(), email.trim()))
+}
 
-    let mut submodule = composite_repo.find_submodule_by_id(child_head_oid)?;
+/// Builds a signature for `--author-*`/`--committer-*`, falling back to the
+/// one of the child commit. `preserve_timestamp` keeps its date.
+fn signature<'a>(
+    name: &Option<String>,
+    email: &Option<String>,
+    inherited: Signature<'a>,
+    preserve_timestamp: bool,
+) -> Result<Signature<'a>> {
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) => (name, email),
+        _ => return Ok(inherited),
+    };
+    let is_valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !email.contains(|c: char| {
+                    c.is_whitespace() || c == '<' || c == '>'
+                })
+        }
+        None => false,
+    };
+    if !is_valid {
+        return Err(PipelineError::InvalidArguments(format!(
+            "Invalid email address {:?}",
+            email
+        )));
+    }
+    if preserve_timestamp {
+        Ok(Signature::new(name, email, &inherited.when())?)
+    } else {
+        Ok(Signature::now(name, email)?)
+    }
+}
 
-    composite_repo.update_submodule_to_id(&mut submodule, child_head_oid)?;
+/// The delay before retry number `retry`: `base`, doubled for every further
+/// retry up to `max`. A `base` above `max` is kept.
+fn backoff(base: Duration, retry: u32, max: Duration) -> Duration {
+    // the factor stays far below an overflow, the maximum is reached anyway
+    let factor = 1 << retry.saturating_sub(1).min(16);
+    base.saturating_mul(factor).min(max.max(base))
+}
 
-    composite_repo.push(&git_ref)?;
+/// Whether a failed network operation is worth retrying. Authentication,
+/// certificate and rejected pushes will fail again the same way.
+fn is_transient(error: &git2::Error) -> bool {
+    matches!(
+        error.class(),
+        ErrorClass::Net | ErrorClass::Os | ErrorClass::Ssl
+    ) && !matches!(
+        error.code(),
+        ErrorCode::Auth | ErrorCode::Certificate | ErrorCode::NotFastForward
+    )
+}
 
-    Ok(())
+/// Reports the transfer progress and once more when it is done. On a
+/// terminal it is a line that is redrawn in place, otherwise a message every
+/// few seconds that is only logged with `--quiet`.
+fn progress_reporter(url: &str, quiet: bool) -> impl FnMut(Progress) {
+    let url = redact::urls(url);
+    let redraw = !quiet && io::stderr().is_terminal();
+    let interval = if redraw {
+        PROGRESS_INTERVAL
+    } else {
+        PROGRESS_LOG_INTERVAL
+    };
+    let mut last_report: Option<Instant> = None;
+    let mut reported_done = false;
+    move |progress| {
+        let done = progress.received_objects() == progress.total_objects();
+        let is_due = last_report.is_none_or(|x| x.elapsed() >= interval);
+        let is_reported = if done { !reported_done } else { is_due };
+        if !is_reported {
+            return;
+        }
+        last_report = Some(Instant::now());
+        reported_done = done;
+        let message = format!(
+            "Fetching {}: {}/{} objects, {} KiB",
+            url,
+            progress.received_objects(),
+            progress.total_objects(),
+            progress.received_bytes() / 1024
+        );
+        if redraw {
+            // \x1b[K clears what is left of a longer previous line
+            eprint!("\r{}\x1b[K{}", message, if done { "\n" } else { "" });
+        } else if quiet {
+            info!(repository:% = url, phase = "fetch"; "{}", message);
+        } else {
+            output::message(Style::Plain, &message);
+        }
+    }
+}
+
+fn trace_headers(headers: &[String], url: &str) {
+    for header in headers {
+        trace!(
+            "Sending {} to {}",
+            redact::header(header),
+            redact::urls(url)
+        );
+    }
+}
+
+fn str_refs(strings: &[String]) -> Vec<&str> {
+    strings.iter().map(|x| x.as_str()).collect()
+}
+
+pub struct RepositoryWrapper<'a> {
+    repository: Repository,
+    args: &'a Args,
+    /// Name of the remote that is pushed to
+    remote: String,
+    /// Overrides the URL of `remote` for the push
+    push_url: Option<String>,
+    /// Reference HEAD is moved back to when a repository of the user was
+    /// used in place
+    restore_head: Option<String>,
+    /// Selects the custom headers for pushing
+    kind: RemoteKind,
+    /// Tag of the child repository that is deployed, it is named in the
+    /// update commits
+    child_tag: Option<String>,
+    /// URL of the child repository's remote for `--match-by url`
+    child_url: Option<String>,
+    #[allow(dead_code)]
+    tempdir: Option<TempDir>,
+}
+
+impl Drop for RepositoryWrapper<'_> {
+    fn drop(&mut self) {
+        let Some(head) = &self.restore_head else {
+            return;
+        };
+        let result = match Oid::from_str(head) {
+            Ok(id) => self.repository.set_head_detached(id),
+            Err(_) => self.repository.set_head(head),
+        };
+        let result = result.and_then(|()| {
+            self.repository.checkout_head(Some(
+                git2::build::CheckoutBuilder::new().force(),
+            ))?;
+            // submodules that were moved go back to the pinned commit
+            for mut submodule in self.repository.submodules()? {
+                if submodule.workdir_id() != submodule.head_id() {
+                    submodule.update(false, None)?;
+                }
+            }
+            self.repository
+                .find_branch(&self.args.temp_branch(), BranchType::Local)?
+                .delete()
+        });
+        if let Err(e) = result {
+            warn!("Could not check out {} again: {}", head, e);
+        }
+    }
+}
+
+impl<'a> RepositoryWrapper<'a> {
+    fn remote_callbacks(args: &'a Args) -> Result<RemoteCallbacks<'a>> {
+        let mut callbacks = auth::remote_callbacks(&args.auth);
+        args.tls.install(&mut callbacks)?;
+        Ok(callbacks)
+    }
+
+    fn fetch_options(
+        args: &'a Args,
+        url: &str,
+        kind: RemoteKind,
+    ) -> Result<FetchOptions<'a>> {
+        let mut fetch_options = FetchOptions::new();
+        let headers = args.custom_headers(url, kind);
+        trace_headers(&headers, url);
+        fetch_options.custom_headers(&str_refs(&headers));
+        let mut callbacks = Self::remote_callbacks(args)?;
+        let deadline = timeout::deadline(args.network_timeout);
+        let mut report_progress =
+            (!args.no_progress).then(|| progress_reporter(url, args.quiet));
+        // returning false aborts the fetch
+        callbacks.transfer_progress(move |progress| {
+            if let Some(report_progress) = &mut report_progress {
+                report_progress(progress);
+            }
+            deadline.is_none_or(|x| Instant::now() < x)
+        });
+        callbacks.sideband_progress(move |text| {
+            let text = String::from_utf8_lossy(text);
+            for line in text.split(['\r', '\n']).filter(|x| !x.is_empty()) {
+                trace!("remote: {}", line);
+            }
+            deadline.is_none_or(|x| Instant::now() < x)
+        });
+        fetch_options.remote_callbacks(callbacks);
+        fetch_options.proxy_options(args.proxy.proxy_options(url));
+        Ok(fetch_options)
+    }
+
+    /// Clones without explaining errors, so a missing `branch` can be told
+    /// apart
+    fn clone_into(
+        args: &'a Args,
+        fetch_url: &str,
+        path: &Path,
+        branch: Option<&str>,
+    ) -> Result<Repository> {
+        let mut fetch_options =
+            Self::fetch_options(args, fetch_url, RemoteKind::Composite)?;
+        if let Some(depth) = args.depth {
+            fetch_options.depth(depth);
+        }
+        // the refspec of `git clone --single-branch`, which keeps the
+        // `<remote>/<branch>` lookup of `checkout_temp_branch` working
+        let refspec = branch.map(|branch| {
+            format!("+refs/heads/{0}:refs/remotes/{1}/{0}", branch, args.remote)
+        });
+        let mut builder = RepoBuilder::new();
+        builder
+            .remote_create(|repository, _, url| match &refspec {
+                Some(refspec) => {
+                    repository.remote_with_fetch(&args.remote, url, refspec)
+                }
+                None => repository.remote(&args.remote, url),
+            })
+            .fetch_options(fetch_options);
+        if let Some(branch) = branch {
+            builder.branch(branch);
+        }
+        Ok(builder.clone(fetch_url, path)?)
+    }
+
+    /// Turns errors of network operations into something more helpful
+    fn explain(args: &Args, error: git2::Error) -> Error {
+        args.auth.explain(error)
+    }
+
+    /// Like `explain`, but names the `phase` that took too long
+    fn explain_timeout(args: &Args, phase: &str, error: git2::Error) -> Error {
+        if !timeout::is_timeout(&error) {
+            return match Self::explain(args, error) {
+                // sockets failing during the network operation
+                PipelineError::Git(error)
+                    if error.class() == ErrorClass::Os =>
+                {
+                    PipelineError::Transport(format!(
+                        "{} failed: {}",
+                        phase,
+                        error.message()
+                    ))
+                }
+                error => error,
+            };
+        }
+        if timeout::overall_expired() {
+            if let Some(seconds) = args.timeout {
+                return PipelineError::Transport(format!(
+                    "{} was aborted, the network operations took longer \
+                    than --timeout {}s",
+                    phase, seconds
+                ));
+            }
+        }
+        match args.network_timeout {
+            Some(seconds) if error.code() == ErrorCode::User => {
+                PipelineError::Transport(format!(
+                    "{} took longer than {}s",
+                    phase, seconds
+                ))
+            }
+            _ => PipelineError::Transport(format!(
+                "{} timed out: {}",
+                phase,
+                error.message()
+            )),
+        }
+    }
+
+    /// Clones only `branch` if it is given and exists, otherwise all
+    /// branches. With `--cache-dir` all branches are fetched into the cached
+    /// clone instead.
+    pub fn clone(
+        url: &str,
+        args: &'a Args,
+        branch: Option<&str>,
+    ) -> Result<Self> {
+        args.auth.check_gitlab_host(url);
+        let fetch_url = git_url::rewrite(&Config::open_default()?, url, false)?;
+
+        let (result, tempdir) = match &args.cache_dir {
+            Some(cache_dir) => {
+                (Self::open_cache(url, &fetch_url, args, cache_dir), None)
+            }
+            None => Self::clone_to_tempdir(&fetch_url, args, branch)?,
+        };
+        let repository = result.map_err(|e| match e {
+            PipelineError::Git(e) => Self::explain_timeout(
+                args,
+                "Cloning the composite repository",
+                e,
+            ),
+            e => e,
+        })?;
+        // like git, keep the original URL and rewrite it on every use
+        repository.remote_set_url(&args.remote, url)?;
+
+        Ok(Self {
+            repository,
+            args,
+            remote: args.remote.clone(),
+            push_url: args.composite_push_url.clone(),
+            restore_head: None,
+            kind: RemoteKind::Composite,
+            child_tag: None,
+            child_url: None,
+            tempdir,
+        })
+    }
+
+    /// Clones into a new temporary directory, which is deleted with the
+    /// wrapper
+    fn clone_to_tempdir(
+        fetch_url: &str,
+        args: &'a Args,
+        branch: Option<&str>,
+    ) -> Result<(Result<Repository>, Option<TempDir>)> {
+        let mut tempdir = tempdir()?;
+        trace!(
+            repository:% = redact::urls(fetch_url), phase = "clone";
+            "Cloning {} into {}",
+            redact::urls(fetch_url),
+            tempdir.path().display()
+        );
+        let result = Self::clone_into(args, fetch_url, tempdir.path(), branch);
+        let result = match (result, branch) {
+            // a branch that is created needs the default branch
+            (Err(PipelineError::Git(e)), Some(branch))
+                if e.code() == ErrorCode::NotFound =>
+            {
+                info!("Branch {} not found, cloning all branches", branch);
+                tempdir = tempfile::tempdir()?;
+                Self::clone_into(args, fetch_url, tempdir.path(), None)
+            }
+            (result, _) => result,
+        };
+        Ok((result, Some(tempdir)))
+    }
+
+    /// Fetches the clone of `url` in `--cache-dir` and resets it, or clones
+    /// it there if there is none or it cannot be used
+    fn open_cache(
+        url: &str,
+        fetch_url: &str,
+        args: &'a Args,
+        cache_dir: &Path,
+    ) -> Result<Repository> {
+        let name: String = redact::urls(url)
+            .chars()
+            .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+            .collect();
+        let path = cache_dir.join(name);
+        let clone = || -> Result<Repository> {
+            trace!(
+                repository:% = redact::urls(fetch_url), phase = "clone";
+                "Cloning {} into {}",
+                redact::urls(fetch_url),
+                path.display()
+            );
+            if path.exists() {
+                fs::remove_dir_all(&path)?;
+            }
+            fs::create_dir_all(&path)?;
+            Self::clone_into(args, fetch_url, &path, None)
+        };
+        let repository = match Repository::open(&path) {
+            Ok(repository)
+                if repository.head().is_ok()
+                    && repository.find_remote(&args.remote).is_ok() =>
+            {
+                repository
+            }
+            _ if !path.exists() => return clone(),
+            _ => {
+                warn!("Replacing {}, it is not a usable clone", path.display());
+                return clone();
+            }
+        };
+
+        info!("Fetching the cached clone in {}", path.display());
+        let mut remote = repository.find_remote(&args.remote)?;
+        let mut options =
+            Self::fetch_options(args, fetch_url, RemoteKind::Composite)?;
+        if let Some(depth) = args.depth {
+            options.depth(depth);
+        }
+        // deleted branches have to disappear to be created again
+        options.prune(FetchPrune::On);
+        remote
+            .fetch(
+                &[&format!("+refs/heads/*:refs/remotes/{}/*", args.remote)],
+                Some(&mut options),
+                None,
+            )
+            .map_err(|e| {
+                Self::explain_timeout(
+                    args,
+                    "Fetching the composite repository",
+                    e,
+                )
+            })?;
+        drop(remote);
+        if let Err(e) = Self::reset_cache(&repository, &args.remote) {
+            warn!("Replacing {}, it cannot be reset: {}", path.display(), e);
+            drop(repository);
+            return clone();
+        }
+        Ok(repository)
+    }
+
+    /// Puts a cached clone back into the state of a fresh clone: only the
+    /// default branch exists locally, at the commit of the remote, and the
+    /// working directory is clean. Temporary branches of earlier runs are
+    /// deleted.
+    fn reset_cache(repository: &Repository, remote: &str) -> Result<()> {
+        let remote_head = repository
+            .find_reference(&format!("refs/remotes/{}/HEAD", remote))?;
+        let default_branch = remote_head
+            .symbolic_target()
+            .and_then(|x| x.strip_prefix(&format!("refs/remotes/{}/", remote)))
+            .ok_or("The cached clone has no default branch")?
+            .to_string();
+        let commit = remote_head.peel_to_commit()?;
+        repository.set_head_detached(commit.id())?;
+        for branch in repository.branches(Some(BranchType::Local))? {
+            branch?.0.delete()?;
+        }
+        repository.branch(&default_branch, &commit, true)?;
+        repository.set_head(&format!("refs/heads/{}", default_branch))?;
+        repository.checkout_head(Some(
+            git2::build::CheckoutBuilder::new()
+                .force()
+                .remove_untracked(true),
+        ))?;
+        repository.reset(commit.as_object(), ResetType::Hard, None)?;
+        Ok(())
+    }
+
+    /// The ref that is deployed: `--git-ref`, the ref of the CI system or
+    /// the branch or tag of HEAD
+    pub(crate) fn git_ref(&self) -> Result<String> {
+        let head = self.repository.head()?;
+        if let Some(git_ref) = &self.args.git_ref {
+            self.full_ref(git_ref)
+        } else if let Some((variable, git_ref)) = self.ci_ref()? {
+            info!("Using {} from {}", git_ref, variable);
+            Ok(git_ref)
+        } else if head.is_branch() {
+            Ok(self.repository.head()?.name().unwrap().to_string())
+        } else if let Some(tag) = self.tag_at(head.peel_to_commit()?.id())? {
+            Ok(format!("refs/tags/{}", tag))
+        } else if self.args.allow_detached {
+            self.detached_branch(head.peel_to_commit()?.id())
+        } else {
+            Err(PipelineError::NotABranch(
+                "No branch name given and HEAD is neither a branch nor a tag, \
+                pass --git-ref or --allow-detached"
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// The ref of the first variable of `CI_REF_VARIABLES` that is set.
+    /// Refs of pull requests are skipped, they are neither branches nor tags.
+    fn ci_ref(&self) -> Result<Option<(&'static str, String)>> {
+        for &variable in CI_REF_VARIABLES {
+            let value = match env::var(variable) {
+                Ok(value) if !value.is_empty() => value,
+                _ => continue,
+            };
+            let is_pull_request = if variable == "GITHUB_REF_NAME" {
+                env::var("GITHUB_REF")
+                    .is_ok_and(|x| x.starts_with("refs/pull/"))
+            } else {
+                value.starts_with("refs/")
+                    && !value.starts_with("refs/heads/")
+                    && !value.starts_with("refs/tags/")
+            };
+            if is_pull_request {
+                trace!(
+                    "Ignoring {}={}, it is not a branch or tag",
+                    variable,
+                    value
+                );
+                continue;
+            }
+            let is_github_tag = variable == "GITHUB_REF_NAME"
+                && env::var("GITHUB_REF_TYPE").is_ok_and(|x| x == "tag");
+            let git_ref = if is_github_tag {
+                format!("refs/tags/{}", value)
+            } else {
+                self.full_ref(&value)?
+            };
+            return Ok(Some((variable, git_ref)));
+        }
+        Ok(None)
+    }
+
+    /// The branch of a detached HEAD at `id` for `--allow-detached`, from
+    /// `GITHUB_REF` or the branches pointing to `id`
+    fn detached_branch(&self, id: Oid) -> Result<String> {
+        if let Ok(git_ref) = env::var("GITHUB_REF") {
+            if git_ref.starts_with("refs/heads/")
+                || git_ref.starts_with("refs/tags/")
+            {
+                trace!("Detached HEAD is {} from GITHUB_REF", git_ref);
+                return Ok(git_ref);
+            }
+        }
+        let remote_prefix = format!("{}/", self.remote);
+        let mut branches = vec![];
+        for branch in self.repository.branches(None)? {
+            let (branch, kind) = branch?;
+            let name = match (branch.name()?, kind) {
+                (Some(name), BranchType::Local) => name,
+                (Some(name), BranchType::Remote) => {
+                    match name.strip_prefix(&remote_prefix) {
+                        Some(name) if name != "HEAD" => name,
+                        _ => continue,
+                    }
+                }
+                (None, _) => continue,
+            };
+            if branch.get().peel_to_commit()?.id() == id
+                && !branches.iter().any(|x| x == name)
+            {
+                branches.push(name.to_string());
+            }
+        }
+        match branches.as_slice() {
+            [branch] => {
+                trace!("Detached HEAD is at the tip of {}", branch);
+                Ok(format!("refs/heads/{}", branch))
+            }
+            [] => Err(PipelineError::NotABranch(format!(
+                "HEAD is detached at {} and no branch points to it, pass \
+                --git-ref",
+                id
+            ))),
+            _ => Err(PipelineError::NotABranch(format!(
+                "HEAD is detached at {} and several branches point to it ({}), \
+                pass --git-ref to pick one",
+                id,
+                branches.join(", ")
+            ))),
+        }
+    }
+
+    /// Turns `--git-ref` into `refs/heads/<branch>` or `refs/tags/<tag>`.
+    /// Remote-tracking branches like `refs/remotes/origin/main` and
+    /// `origin/main` name the branch. Other short names are branches unless
+    /// there is only a tag of that name.
+    fn full_ref(&self, git_ref: &str) -> Result<String> {
+        let exists = |name: &str| self.repository.find_reference(name).is_ok();
+        let full_ref = if git_ref.starts_with("refs/heads/")
+            || git_ref.starts_with("refs/tags/")
+        {
+            git_ref.to_string()
+        } else if let Some(remote_branch) =
+            git_ref.strip_prefix("refs/remotes/")
+        {
+            let (_, branch) = remote_branch
+                .split_once('/')
+                .ok_or_else(|| PipelineError::InvalidGitRef(git_ref.into()))?;
+            format!("refs/heads/{}", branch)
+        } else if git_ref.starts_with("refs/") || git_ref.is_empty() {
+            return Err(PipelineError::InvalidGitRef(git_ref.to_string()));
+        } else {
+            let remotes = self.repository.remotes()?;
+            let branch = match git_ref.split_once('/') {
+                Some((remote, branch))
+                    if remotes.iter().flatten().any(|x| x == remote)
+                        && !exists(&format!("refs/heads/{}", git_ref)) =>
+                {
+                    branch
+                }
+                _ => git_ref,
+            };
+            let is_tag = branch == git_ref
+                && exists(&format!("refs/tags/{}", git_ref))
+                && !exists(&format!("refs/heads/{}", git_ref))
+                && !exists(&format!(
+                    "refs/remotes/{}/{}",
+                    self.remote, git_ref
+                ));
+            if is_tag {
+                format!("refs/tags/{}", git_ref)
+            } else {
+                format!("refs/heads/{}", branch)
+            }
+        };
+        if !git2::Reference::is_valid_name(&full_ref) {
+            return Err(PipelineError::InvalidGitRef(git_ref.to_string()));
+        }
+        if full_ref != git_ref {
+            trace!("--git-ref {} is {}", git_ref, full_ref);
+        }
+        Ok(full_ref)
+    }
+
+    /// Name of a tag pointing to `id`
+    fn tag_at(&self, id: Oid) -> Result<Option<String>> {
+        for tag in self.repository.tag_names(None)?.iter().flatten() {
+            let reference = self
+                .repository
+                .find_reference(&format!("refs/tags/{}", tag))?;
+            if reference.peel_to_commit()?.id() == id {
+                return Ok(Some(tag.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The commit to update to, HEAD or `--commit`, checked against the tip
+    /// of `git_ref`
+    pub(crate) fn head_id(&self, git_ref: &str) -> Result<Oid> {
+        // CI checkouts often only have the remote-tracking branch
+        let reference = match self.repository.find_reference(git_ref) {
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                match git_ref.strip_prefix("refs/heads/") {
+                    Some(branch) => self.repository.find_reference(
+                        &format!("refs/remotes/{}/{}", self.remote, branch),
+                    )?,
+                    None => return Err(e.into()),
+                }
+            }
+            result => result?,
+        };
+        let commit = reference.peel_to_commit()?;
+        if let Some(sha) = &self.args.commit {
+            return self.explicit_commit(sha, commit.id(), git_ref);
+        }
+        if self.repository.head()?.peel_to_commit()?.id() == commit.id() {
+            Ok(commit.id())
+        } else {
+            Err(PipelineError::HeadMismatch(git_ref.to_string()))
+        }
+    }
+
+    /// Resolves `--commit`, which is expected to be part of the history of
+    /// `git_ref` at `ref_id`
+    fn explicit_commit(
+        &self,
+        sha: &str,
+        ref_id: Oid,
+        git_ref: &str,
+    ) -> Result<Oid> {
+        let id = self
+            .repository
+            .revparse_single(sha)
+            .and_then(|x| x.peel_to_commit())
+            .map_err(|_| {
+                PipelineError::InvalidArguments(format!(
+                    "Commit {} not found in the child repository",
+                    sha
+                ))
+            })?
+            .id();
+        if id != ref_id && !self.repository.graph_descendant_of(ref_id, id)? {
+            self.args.fallback(&format!(
+                "Commit {} is not part of the history of {}",
+                id, git_ref
+            ))?;
+        }
+        Ok(id)
+    }
+
+    pub(crate) fn head_commit(&self) -> Result<Oid> {
+        Ok(self.repository.head()?.peel_to_commit()?.id())
+    }
+
+    /// The submodules that changed since `base`
+    fn changed_submodules(&self, base: Oid) -> Result<Vec<SubmoduleUpdate>> {
+        let base_tree = self.repository.find_commit(base)?.tree()?;
+        let head_tree = self.repository.head()?.peel_to_tree()?;
+        let diff = self.repository.diff_tree_to_tree(
+            Some(&base_tree),
+            Some(&head_tree),
+            None,
+        )?;
+        Ok(diff
+            .deltas()
+            .filter(|x| x.new_file().mode() == FileMode::Commit)
+            .filter_map(|x| {
+                Some(SubmoduleUpdate {
+                    path: x.new_file().path()?.display().to_string(),
+                    previous_commit: x.old_file().id().to_string(),
+                    new_commit: x.new_file().id().to_string(),
+                })
+            })
+            .collect())
+    }
+
+    pub fn open(path: &str, args: &'a Args) -> Result<Self> {
+        let repository = Repository::open(path)?;
+        Ok(Self {
+            repository,
+            args,
+            remote: args.remote.clone(),
+            push_url: None,
+            restore_head: None,
+            kind: RemoteKind::Child,
+            child_tag: None,
+            child_url: None,
+            tempdir: None,
+        })
+    }
+
+    /// Clones the composite repository, or opens it in place with
+    /// `--composite-no-clone`
+    pub(crate) fn open_composite(
+        args: &'a Args,
+        branch: Option<&str>,
+    ) -> Result<Self> {
+        // stalled connections do not call back, so they need a socket
+        // timeout to honour --timeout
+        let shortest = |x: Option<u64>| match (x, args.timeout) {
+            (Some(x), Some(timeout)) => Some(x.min(timeout)),
+            (x, timeout) => x.or(timeout),
+        };
+        timeout::set_server_timeouts(
+            shortest(args.connect_timeout),
+            shortest(args.network_timeout),
+        )?;
+        timeout::start_overall(args.timeout);
+        if !args.composite_no_clone {
+            return Self::clone(args.composite_repository(), args, branch);
+        }
+        let mut wrapper = Self::open(args.composite_repository(), args)?;
+        wrapper.push_url = args.composite_push_url.clone();
+        wrapper.kind = RemoteKind::Composite;
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(false);
+        if !wrapper.repository.statuses(Some(&mut options))?.is_empty() {
+            return Err(format!(
+                "{} has uncommitted changes, commit or stash them first",
+                args.composite_repository()
+            )
+            .into());
+        }
+        let head = wrapper.repository.head()?;
+        let restore_head = match head.name() {
+            Some(name) if head.is_branch() => name.to_string(),
+            _ => head.peel_to_commit()?.id().to_string(),
+        };
+        drop(head);
+        wrapper.restore_head = Some(restore_head);
+        Ok(wrapper)
+    }
+
+    /// Opens a submodule that is updated itself. The temporary branch is
+    /// checked out at `git_ref` of its origin, so the update can be committed
+    /// and pushed on top of it.
+    fn open_submodule(
+        submodule: &Submodule,
+        args: &'a Args,
+        git_ref: &str,
+    ) -> Result<Self> {
+        let wrapper = Self {
+            repository: submodule.open()?,
+            args,
+            remote: "origin".to_string(),
+            push_url: None,
+            restore_head: None,
+            kind: RemoteKind::Child,
+            child_tag: None,
+            child_url: None,
+            tempdir: None,
+        };
+        wrapper.checkout_temp_branch(git_ref)?;
+        Ok(wrapper)
+    }
+
+    /// The branch of the composite repository that is updated for the
+    /// `git_ref` of the child repository. Branches map to the branch of the
+    /// same name, tags to the default branch of the composite repository.
+    pub(crate) fn target_ref(&self, git_ref: &str) -> Result<String> {
+        if let Some(target_branch) = &self.args.target_branch {
+            Ok(format!("refs/heads/{}", target_branch))
+        } else if let Some(mapped) = self.args.map_ref(git_ref)? {
+            info!("{} maps to {}", git_ref, mapped);
+            Ok(mapped)
+        } else if let Some(tag) = git_ref.strip_prefix("refs/tags/") {
+            let head = self.repository.head()?;
+            let target_ref = head
+                .name()
+                .filter(|_| head.is_branch())
+                .ok_or("The composite repository has no default branch")?;
+            info!("{} is a tag, updating {}", tag, target_ref);
+            Ok(target_ref.to_string())
+        } else {
+            Ok(git_ref.to_string())
+        }
+    }
+
+    /// The commit the default branch of the remote points to, or HEAD if the
+    /// remote's default branch is unknown
+    fn default_branch_commit(&self) -> Result<Commit<'_>> {
+        let remote_head = format!("refs/remotes/{}/HEAD", self.remote);
+        let reference = match self.repository.find_reference(&remote_head) {
+            Ok(reference) => reference,
+            Err(_) => {
+                let head = self.repository.head()?;
+                self.args.fallback(&format!(
+                    "The default branch of {} is unknown, using {}",
+                    self.remote,
+                    head.shorthand().unwrap_or("HEAD")
+                ))?;
+                head
+            }
+        };
+        Ok(reference.peel_to_commit()?)
+    }
+
+    /// The commit of `--create-missing-branch=<base>`, a branch of the remote
+    /// or anything else git can resolve like a tag or a commit id
+    fn base_commit(&self, base: &str) -> Result<Commit<'_>> {
+        let branch = base.strip_prefix("refs/heads/").unwrap_or(base);
+        let remote_branch = format!("refs/remotes/{}/{}", self.remote, branch);
+        let object = match self.repository.find_reference(&remote_branch) {
+            Ok(reference) => reference.peel(ObjectType::Commit)?,
+            Err(_) => self.repository.revparse_single(base).map_err(|e| {
+                PipelineError::InvalidArguments(format!(
+                    "Base {} of the new branch not found: {}",
+                    base,
+                    e.message()
+                ))
+            })?,
+        };
+        Ok(object.peel_to_commit()?)
+    }
+
+    /// branch_name is the full branch name containing the remote name (i.e. `refs/heads/main`)
+    pub fn checkout_temp_branch(&self, git_ref: &str) -> Result<()> {
+        trace!(
+            repository:% = self.repository.path().display(), phase = "checkout";
+            "Checking out {} in {}",
+            git_ref,
+            self.repository.path().display()
+        );
+        let branch_name = Self::get_branch_name_from_ref(git_ref)?;
+        let branch = self.repository.find_branch(
+            &format!("{}/{}", self.remote, branch_name),
+            BranchType::Remote,
+        );
+        let commit = match branch {
+            Ok(branch) => {
+                trace!("Found reference {}.", git_ref);
+                branch.get().peel_to_commit()?
+            }
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                let Some(base) =
+                    optional_value(&self.args.create_missing_branch)
+                else {
+                    return Err(PipelineError::MissingBranch(format!(
+                        "Branch {} does not exist on {}, pass \
+                        --create-missing-branch to create it",
+                        branch_name, self.remote
+                    )));
+                };
+                self.args.print(&format!(
+                    "Creating branch {} from {}",
+                    branch_name,
+                    base.unwrap_or("the default branch")
+                ));
+                match base {
+                    Some(base) => self.base_commit(base)?,
+                    None => self.default_branch_commit()?,
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let temp_branch = self.args.temp_branch();
+        trace!(
+            "Creating {} branch in {:?}",
+            temp_branch,
+            self.repository.path()
+        );
+        self.repository.branch(&temp_branch, &commit, true)?;
+        // relative submodule URLs are resolved against the remote of the
+        // branch's upstream
+        let mut config = self.repository.config()?;
+        config
+            .set_str(&format!("branch.{}.remote", temp_branch), &self.remote)?;
+        config.set_str(
+            &format!("branch.{}.merge", temp_branch),
+            &format!("refs/heads/{}", branch_name),
+        )?;
+        self.repository
+            .set_head(&format!("refs/heads/{}", temp_branch))?;
+        self.repository.checkout_head(None)?;
+        // the checkout keeps the gitlinks of the previous HEAD in the index,
+        // which would revert other submodules with the update commit
+        let mut index = self.repository.index()?;
+        index.read_tree(&commit.tree()?)?;
+        index.write()?;
+        Ok(())
+    }
+
+    /// Fetches the target branch again and recreates the temporary branch on
+    /// its new tip for `--retry-on-conflict`
+    fn reset_temp_branch(&self, git_ref: &str) -> Result<()> {
+        let branch_name = Self::get_branch_name_from_ref(git_ref)?;
+        let mut remote = self.repository.find_remote(&self.remote)?;
+        let url = remote.url().unwrap_or_default().to_string();
+        let mut options = Self::fetch_options(self.args, &url, self.kind)?;
+        remote
+            .fetch(
+                &[&format!(
+                    "+refs/heads/{0}:refs/remotes/{1}/{0}",
+                    branch_name, self.remote
+                )],
+                Some(&mut options),
+                None,
+            )
+            .map_err(|e| Self::explain(self.args, e))?;
+        // the branch that HEAD points to cannot be replaced
+        let head = self.repository.head()?.peel_to_commit()?;
+        self.repository.set_head_detached(head.id())?;
+        self.checkout_temp_branch(git_ref)
+    }
+
+    /// Finds the submodules to update, either the one at `--submodule-path`
+    /// or all the ones containing `id`
+    pub(crate) fn find_submodules(
+        &self,
+        id: Oid,
+    ) -> Result<Vec<Submodule<'_>>> {
+        match (&self.args.submodule_path, self.args.match_by) {
+            (Some(path), _) => Ok(vec![self.find_submodule_by_path(path, id)?]),
+            (None, MatchBy::Url) => self.find_submodules_by_url(id),
+            (None, MatchBy::Commit) => self.find_submodules_by_id(id),
+        }
+    }
+
+    /// Remembers what the update needs to know about the child repository
+    fn set_child(&mut self, child: &RepositoryWrapper, child_ref: &str) {
+        self.child_tag = tag_name(child_ref);
+        self.child_url = child
+            .repository
+            .find_remote(&child.remote)
+            .ok()
+            .and_then(|x| x.url().map(|x| x.to_string()));
+    }
+
+    /// Looks up the one submodule with the URL of the child repository's
+    /// remote, or all of them with `--all-matching`. The commit is fetched
+    /// explicitly if it is not on a branch.
+    fn find_submodules_by_url(&self, id: Oid) -> Result<Vec<Submodule<'_>>> {
+        let child_url = self.child_url.as_deref().ok_or_else(|| {
+            PipelineError::InvalidArguments(format!(
+                "--match-by url needs the remote {} of the child repository",
+                self.args.remote
+            ))
+        })?;
+        let normalized = git_url::normalize(child_url);
+        let mut candidates = vec![];
+        let mut matches = vec![];
+        for mut submodule in self.repository.submodules()? {
+            let url = self.submodule_url(&mut submodule)?;
+            candidates.push(format!(
+                "  {} ({})",
+                submodule.path().display(),
+                redact::urls(&url)
+            ));
+            if git_url::normalize(&url) == normalized {
+                matches.push(submodule);
+            }
+        }
+        if matches.is_empty() || matches.len() > 1 && !self.args.all_matching {
+            let found = match matches.len() {
+                0 => "No submodule has".to_string(),
+                n => format!("{} submodules have", n),
+            };
+            return Err(PipelineError::SubmoduleNotFound(format!(
+                "{} the URL {} of the child repository, pass \
+                --submodule-path to pick one or --all-matching to update \
+                all of them. Submodules:\n{}",
+                found,
+                redact::urls(child_url),
+                candidates.join("\n")
+            )));
+        }
+        for submodule in &mut matches {
+            self.fetch_submodule_commit(submodule, id)?;
+            info!(
+                submodule:% = submodule.path().display(), phase = "search";
+                "Found submodule by URL: {:?}",
+                submodule.path()
+            );
+        }
+        Ok(matches)
+    }
+
+    /// Updates a submodule found by URL and fetches `id` explicitly if it
+    /// is not on a branch
+    fn fetch_submodule_commit(
+        &self,
+        submodule: &mut Submodule,
+        id: Oid,
+    ) -> Result<()> {
+        let path = submodule.path().display().to_string();
+        let url = self.submodule_url(submodule)?;
+        Self::fetch_submodule(self.args, submodule, &url)?;
+        let repository = submodule.open()?;
+        if repository.find_commit(id).is_err() {
+            trace!("Fetching {} into submodule {}", id, path);
+            let mut options =
+                Self::fetch_options(self.args, &url, RemoteKind::Child)?;
+            repository
+                .find_remote("origin")?
+                .fetch(&[&id.to_string()], Some(&mut options), None)
+                .map_err(|e| {
+                    PipelineError::SubmoduleNotFound(format!(
+                        "Submodule {} has the URL of the child repository, \
+                        but commit {} could not be fetched from it: {}",
+                        path,
+                        id,
+                        e.message()
+                    ))
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Looks up the submodule at `path` and makes sure it contains `id`
+    fn find_submodule_by_path(
+        &self,
+        path: &str,
+        id: Oid,
+    ) -> Result<Submodule<'_>> {
+        let mut submodule =
+            self.repository.find_submodule(path).map_err(|e| {
+                PipelineError::SubmoduleNotFound(format!(
+                    "No submodule at {}: {}",
+                    path, e
+                ))
+            })?;
+        let is_local = self.args.no_submodule_update
+            && submodule.open().is_ok_and(|x| x.find_commit(id).is_ok());
+        if !is_local {
+            self.update_submodule(&mut submodule)?;
+        }
+        if submodule.open()?.find_commit(id).is_err() {
+            return Err(PipelineError::SubmoduleNotFound(format!(
+                "Submodule {} does not contain commit {}",
+                path, id
+            )));
+        }
+        info!(
+            submodule:% = submodule.path().display(), phase = "search";
+            "Found submodule: {:?}",
+            submodule.path()
+        );
+        Ok(submodule)
+    }
+
+    /// The same child repository may be embedded at several paths, all of
+    /// them are returned
+    fn find_submodules_by_id(&self, id: Oid) -> Result<Vec<Submodule<'_>>> {
+        let mut submodules = self.repository.submodules()?;
+        let contains_id =
+            |x: &Submodule| x.open().is_ok_and(|x| x.find_commit(id).is_ok());
+        let is_local =
+            self.args.no_submodule_update && submodules.iter().any(contains_id);
+        if is_local {
+            trace!("Found {} without fetching submodules", id);
+        } else {
+            self.fetch_submodules(&mut submodules)?;
+        }
+        // the commits the composite repository pins, for the error message
+        let searched: Vec<_> = submodules
+            .iter()
+            .map(|x| {
+                let pinned = x.head_id().map_or("no commit".to_string(), |x| {
+                    x.to_string()[..10].to_string()
+                });
+                format!("  {} at {}", x.path().display(), pinned)
+            })
+            .collect();
+        let mut found = vec![];
+        for submodule in submodules {
+            trace!("Found submodule {}", submodule.path().display());
+            match submodule.open() {
+                Ok(repository) if repository.find_commit(id).is_ok() => {
+                    drop(repository);
+                    found.push(submodule);
+                }
+                Ok(_) => {}
+                // submodules that were not fetched are not checked out yet
+                Err(_) if is_local => {}
+                Err(e) => self.args.fallback(&format!(
+                    "Skipping submodule {}, it could not be opened: {}",
+                    submodule.path().display(),
+                    e.message()
+                ))?,
+            }
+        }
+        let submodules = found;
+        if submodules.is_empty() {
+            let mut message = match self.args.depth {
+                Some(depth) => format!(
+                    "No submodule found containing {} in the last {} commits \
+                    of the composite repository",
+                    id, depth
+                ),
+                None => format!("No submodule found containing {}", id),
+            };
+            if searched.is_empty() {
+                message.push_str(", the composite repository has none");
+            } else {
+                message.push_str(&format!(
+                    ". Searched:\n{}\nThe commit may not have been pushed to \
+                    the remote of its submodule yet.",
+                    searched.join("\n")
+                ));
+            }
+            return Err(PipelineError::SubmoduleNotFound(message));
+        }
+        for submodule in &submodules {
+            info!(
+                submodule:% = submodule.path().display(), phase = "search";
+                "Found submodule: {:?}",
+                submodule.path()
+            );
+        }
+        Ok(submodules)
+    }
+
+    /// Initializes and fetches the submodule using the same headers and
+    /// credentials as the composite repository
+    fn update_submodule(&self, submodule: &mut Submodule) -> Result<()> {
+        let url = self.submodule_url(submodule)?;
+        Self::fetch_submodule(self.args, submodule, &url)
+    }
+
+    /// Clones or fetches an initialized submodule from `url`. It does not
+    /// touch the composite repository's config, so it is safe to run for
+    /// several submodules in parallel.
+    fn fetch_submodule(
+        args: &'a Args,
+        submodule: &mut Submodule,
+        url: &str,
+    ) -> Result<()> {
+        let phase =
+            format!("Fetching submodule {}", submodule.path().display());
+        let is_cloned = submodule.open().is_ok();
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(Self::fetch_options(args, url, RemoteKind::Child)?);
+        submodule
+            .update(true, Some(&mut options))
+            .map_err(|e| Self::explain_timeout(args, &phase, e))?;
+        // an existing clone is only fetched if the pinned commit is missing
+        if is_cloned {
+            let mut options =
+                Self::fetch_options(args, url, RemoteKind::Child)?;
+            submodule
+                .open()?
+                .find_remote("origin")?
+                .fetch::<&str>(&[], Some(&mut options), None)
+                .map_err(|e| Self::explain_timeout(args, &phase, e))?;
+        }
+        Ok(())
+    }
+
+    /// Fetches all `submodules` with up to `--jobs` threads. git2 objects
+    /// cannot be shared between threads, so every worker opens the
+    /// composite repository on its own and looks the submodules up by name.
+    fn fetch_submodules(&self, submodules: &mut [Submodule]) -> Result<()> {
+        let mut work = Vec::new();
+        for submodule in submodules.iter_mut() {
+            let url = self.submodule_url(submodule)?;
+            let name = submodule.name().ok_or("Submodule name is not UTF-8")?;
+            work.push((name.to_string(), url));
+        }
+        let jobs = (self.args.jobs as usize).min(work.len());
+        if jobs <= 1 {
+            for (submodule, (_, url)) in submodules.iter_mut().zip(&work) {
+                Self::fetch_submodule(self.args, submodule, url)?;
+            }
+            return Ok(());
+        }
+
+        let queue = Mutex::new(work.iter());
+        let path = self.repository.path();
+        let args = self.args;
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| -> Result<()> {
+                        let repository = Repository::open(path)?;
+                        loop {
+                            let next = queue.lock().unwrap().next();
+                            let Some((name, url)) = next else {
+                                return Ok(());
+                            };
+                            let mut submodule =
+                                repository.find_submodule(name)?;
+                            Self::fetch_submodule(args, &mut submodule, url)?;
+                        }
+                    })
+                })
+                .collect();
+            // the scope waits for the remaining workers on an early return
+            workers.into_iter().try_for_each(|x| {
+                x.join().unwrap_or_else(|_| {
+                    Err("Fetching a submodule panicked".into())
+                })
+            })
+        })
+    }
+
+    /// All submodules of HEAD, without fetching them
+    pub(crate) fn submodules(&self) -> Result<Vec<Submodule<'_>>> {
+        Ok(self.repository.submodules()?)
+    }
+
+    /// Initializes the submodule and returns its resolved and rewritten URL.
+    /// The rewritten URL is not written to the config, as it may carry
+    /// credentials. libgit2 applies the same rewrite when it creates the
+    /// remote of the submodule.
+    pub(crate) fn submodule_url(
+        &self,
+        submodule: &mut Submodule,
+    ) -> Result<String> {
+        submodule.init(false)?;
+        let name = submodule.name().ok_or("Submodule name is not UTF-8")?;
+        let config = self.repository.config()?;
+        let url = config.get_string(&format!("submodule.{}.url", name))?;
+        git_url::rewrite(&config, &url, false)
+    }
+
+    /// URLs of all submodules, without fetching them
+    pub(crate) fn submodule_urls(&self) -> Result<Vec<String>> {
+        let mut urls = vec![];
+        for mut submodule in self.repository.submodules()? {
+            urls.push(self.submodule_url(&mut submodule)?);
+        }
+        Ok(urls)
+    }
+
+    /// Writes the commits that a push would send to --patch-file
+    fn write_patch(&self, git_ref_target: &str) -> Result<()> {
+        let branch_name = Self::get_branch_name_from_ref(git_ref_target)?;
+        let path = self.args.patch_file.as_deref().ok_or_else(|| {
+            PipelineError::InvalidArguments(
+                "--no-push requires --patch-file".to_string(),
+            )
+        })?;
+        let commits = patch::new_commits(&self.repository, &self.remote)?;
+        match self.args.patch_format {
+            PatchFormat::Mbox => {
+                patch::write_mbox(&self.repository, &commits, path)?
+            }
+            PatchFormat::Bundle => {
+                // the local branch of a clone is not used otherwise, but
+                // the one of --composite-no-clone belongs to the user
+                if self.args.composite_no_clone {
+                    return Err(PipelineError::InvalidArguments(
+                        "--patch-format bundle cannot be used with \
+                        --composite-no-clone"
+                            .to_string(),
+                    ));
+                }
+                let reference = format!("refs/heads/{}", branch_name);
+                self.repository.reference(
+                    &reference,
+                    self.head_commit()?,
+                    true,
+                    "update to bundle",
+                )?;
+                patch::write_bundle(
+                    &self.repository,
+                    &reference,
+                    &self.remote,
+                    path,
+                )?;
+            }
+        }
+        self.args.print_styled(
+            Style::Success,
+            &format!(
+                "Wrote {} commit(s) for {} to {}",
+                commits.len(),
+                branch_name,
+                path.display()
+            ),
+        );
+        Ok(())
+    }
+
+    /// Connects to `url` like `git ls-remote` without fetching anything
+    pub(crate) fn check_fetch(
+        &self,
+        url: &str,
+        kind: RemoteKind,
+    ) -> Result<()> {
+        let mut remote = self.repository.remote_anonymous(url)?;
+        let mut options = Self::fetch_options(self.args, url, kind)?;
+        remote
+            .fetch::<&str>(&[], Some(&mut options), None)
+            .map_err(|e| Self::explain(self.args, e))?;
+        Ok(())
+    }
+
+    /// Connects to `url` like `check_fetch`, but without a clone of the
+    /// composite repository. An empty repository is needed for the fetch,
+    /// which sends the custom headers unlike `Remote::connect`.
+    pub(crate) fn check_url(url: &str, args: &'a Args) -> Result<()> {
+        args.auth.check_gitlab_host(url);
+        let fetch_url = git_url::rewrite(&Config::open_default()?, url, false)?;
+        let tempdir = tempdir()?;
+        let repository = Repository::init_bare(tempdir.path())?;
+        let mut remote = repository.remote_anonymous(&fetch_url)?;
+        let mut options =
+            Self::fetch_options(args, &fetch_url, RemoteKind::Composite)?;
+        // without refspecs everything would be downloaded, this one lists
+        // the refs and matches none of them
+        remote
+            .fetch(
+                &["refs/validate/*:refs/validate/*"],
+                Some(&mut options),
+                None,
+            )
+            .map_err(|e| {
+                Self::explain_timeout(
+                    args,
+                    "Connecting to the composite repository",
+                    e,
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Whether `id` is on a branch or tag of the child repository at `url`.
+    /// All of them are fetched into an empty repository for that.
+    pub(crate) fn commit_on_remote(&self, url: &str, id: Oid) -> Result<bool> {
+        let tempdir = tempdir()?;
+        let repository = Repository::init_bare(tempdir.path())?;
+        let mut remote = repository.remote_anonymous(url)?;
+        let mut options =
+            Self::fetch_options(self.args, url, RemoteKind::Child)?;
+        remote
+            .fetch(
+                &["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"],
+                Some(&mut options),
+                None,
+            )
+            .map_err(|e| Self::explain(self.args, e))?;
+        let found = repository.find_commit(id).is_ok();
+        Ok(found)
+    }
+
+    /// Connects to `url` for pushing without updating any reference
+    pub(crate) fn check_push(&self, url: &str) -> Result<()> {
+        let mut remote = self.repository.remote_anonymous(url)?;
+        let mut options = self.push_options(url)?;
+        remote
+            .push::<&str>(&[], Some(&mut options))
+            .map_err(|e| Self::explain(self.args, e))?;
+        Ok(())
+    }
+
+    /// The URL `push` pushes to. libgit2 only applies insteadOf to the URL
+    /// of a remote, so this starts from the configured one.
+    pub(crate) fn push_url(&self) -> Result<String> {
+        let config = self.repository.config()?;
+        let url = match &self.push_url {
+            Some(push_url) => push_url.clone(),
+            None => {
+                config.get_string(&format!("remote.{}.url", self.remote))?
+            }
+        };
+        git_url::rewrite(&config, &url, true)
+    }
+
+    /// Searches `id` in the submodules of the submodules. The innermost
+    /// submodule is updated and every level above gets a commit pointing to
+    /// the new nested commit. Returns the submodules that were committed to,
+    /// innermost first.
+    fn update_nested_submodule(
+        &self,
+        id: Oid,
+        git_ref: &str,
+    ) -> Result<Vec<Self>> {
+        for submodule in self.repository.submodules()? {
+            let mut nested =
+                match Self::open_submodule(&submodule, self.args, git_ref) {
+                    Ok(nested) => nested,
+                    // siblings need not have the branch of the child
+                    Err(PipelineError::MissingBranch(message)) => {
+                        self.args.fallback(&format!(
+                            "Skipping submodule {}: {}",
+                            submodule.path().display(),
+                            message
+                        ))?;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+            nested.child_tag = self.child_tag.clone();
+            nested.child_url = self.child_url.clone();
+            trace!("Searching nested submodules of {:?}", submodule.path());
+            let mut updated = match nested.find_submodules_by_id(id) {
+                Ok(mut nested_submodules) => {
+                    nested
+                        .update_submodules_to_id(&mut nested_submodules, id)?;
+                    vec![]
+                }
+                Err(_) => match nested.update_nested_submodule(id, git_ref) {
+                    Ok(updated) => updated,
+                    Err(_) => continue,
+                },
+            };
+            info!(
+                submodule:% = submodule.path().display(), phase = "update";
+                "Updating intermediate submodule {:?}",
+                submodule.path()
+            );
+            let mut submodule = submodule;
+            submodule.add_to_index(true)?;
+            if submodule.index_id() == submodule.head_id()
+                && !self.args.allow_empty
+            {
+                info!(
+                    submodule:% = submodule.path().display(), phase = "update";
+                    "{:?} is already up to date",
+                    submodule.path()
+                );
+                return Ok(updated);
+            }
+            self.commit(&[&submodule], id)?;
+            updated.push(nested);
+            return Ok(updated);
+        }
+        Err(PipelineError::SubmoduleNotFound(
+            "No nested submodule found".to_string(),
+        ))
+    }
+
+    /// Creates one commit per submodule, or a single one with
+    /// `--combine-commits`. Submodules that already point to `id` are
+    /// skipped unless `--allow-empty` is given.
+    fn update_submodules_to_id(
+        &self,
+        submodules: &mut [Submodule],
+        id: Oid,
+    ) -> Result<()> {
+        let mut updated = vec![];
+        for submodule in submodules.iter_mut() {
+            if submodule.head_id() == Some(id) && !self.args.allow_empty {
+                info!(
+                    submodule:% = submodule.path().display(), phase = "update";
+                    "{:?} is already up to date at {}",
+                    submodule.path(),
+                    id
+                );
+                continue;
+            }
+            let sub_repository = submodule.open()?;
+            let commit = sub_repository.find_commit(id)?;
+            info!("Found commit: {:?}", commit);
+            sub_repository.set_head_detached(commit.id())?;
+            if self.args.lfs && lfs::is_used(&sub_repository, &commit)? {
+                sub_repository.checkout_head(Some(
+                    git2::build::CheckoutBuilder::new().force(),
+                ))?;
+                let workdir = sub_repository.workdir().ok_or_else(|| {
+                    format!("{:?} has no working directory", submodule.path())
+                })?;
+                lfs::pull(workdir)?;
+            }
+            submodule.add_to_index(true)?;
+            info!(
+                submodule:% = submodule.path().display(), phase = "update";
+                "Updated {:?}",
+                sub_repository.path()
+            );
+            if !self.args.combine_commits {
+                self.commit(&[submodule], id)?;
+            }
+            updated.push(&*submodule);
+        }
+        if self.args.combine_commits && !updated.is_empty() {
+            self.commit(&updated, id)?;
+        }
+        Ok(())
+    }
+
+    /// All `submodules` point to the same commit, their paths are listed in
+    /// the message. `child_id` is the deployed child commit, which nested
+    /// submodules only point to indirectly.
+    fn commit(&self, submodules: &[&Submodule], child_id: Oid) -> Result<()> {
+        let mut index = self.repository.index()?;
+        let tree_id = index.write_tree()?;
+        let tree = self.repository.find_tree(tree_id)?;
+        let submodule_repo = submodules[0].open()?;
+        let submodule_commit = submodule_repo.head()?.peel_to_commit()?;
+        let submodule_path = submodules
+            .iter()
+            .map(|x| x.path().display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let commit_id = submodule_commit.id().to_string();
+        let original_message = submodule_commit.message().unwrap();
+        let tag = self.child_tag.as_deref();
+        let author = signature(
+            &self.args.author_name,
+            &self.args.author_email,
+            submodule_commit.author(),
+            self.args.preserve_timestamps,
+        )?;
+        let committer = signature(
+            &self.args.committer_name,
+            &self.args.committer_email,
+            submodule_commit.committer(),
+            self.args.preserve_timestamps,
+        )?;
+        let trailers = self.trailers(child_id, &committer);
+        // git interpret-trailers stops at a `---` line
+        let separator = if trailers.is_empty() {
+            "\n---\n"
+        } else {
+            "\n\n"
+        };
+        let message = match &self.args.message_template {
+            Some(template) => template
+                .replace("{submodule}", &submodule_path)
+                .replace("{commit}", &commit_id)
+                .replace("{short_commit}", &commit_id[..7])
+                .replace("{tag}", tag.unwrap_or_default())
+                .replace("{original_message}", original_message),
+            None => match tag {
+                Some(tag) => format!(
+                    "Update submodule {} to {} ({}){}{}",
+                    submodule_path, tag, commit_id, separator, original_message
+                ),
+                None => format!(
+                    "Update submodule {} to {}{}{}",
+                    submodule_path, commit_id, separator, original_message
+                ),
+            },
+        };
+        if message.trim().is_empty() {
+            return Err("The rendered commit message is empty".into());
+        }
+        let message = if trailers.is_empty() {
+            message
+        } else {
+            format!("{}\n\n{}\n", message.trim_end(), trailers.join("\n"))
+        };
+        self.commit_tree(&author, &committer, &message, &tree)?;
+        Ok(())
+    }
+
+    /// `--trailer`, the `--standard-trailers` for an update to `child_id`
+    /// and `--sign-off`, where an empty value stands for `committer`
+    fn trailers(&self, child_id: Oid, committer: &Signature) -> Vec<String> {
+        let mut trailers = self.args.trailer.clone();
+        if self.args.standard_trailers {
+            trailers.push(format!("Child-Commit: {}", child_id));
+            if let Some(url) = &self.child_url {
+                trailers
+                    .push(format!("Child-Repository: {}", redact::urls(url)));
+            }
+            trailers.push(format!(
+                "Deployment-Tool-Version: {}",
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        for sign_off in &self.args.sign_off {
+            let trailer = if sign_off.is_empty() {
+                format!(
+                    "Signed-off-by: {} <{}>",
+                    committer.name().unwrap_or_default(),
+                    committer.email().unwrap_or_default()
+                )
+            } else {
+                format!("Signed-off-by: {}", sign_off)
+            };
+            if !trailers.contains(&trailer) {
+                trailers.push(trailer);
+            }
+        }
+        trailers
+    }
+
+    /// Commits `tree` on top of HEAD and moves HEAD to it, signed with
+    /// `--gpg-sign` or `--sign-with-ssh-key`
+    fn commit_tree(
+        &self,
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+        tree: &Tree,
+    ) -> Result<Oid> {
+        let commit = self.repository.head()?.peel_to_commit()?;
+        if self.args.gpg_sign.is_none() && self.args.sign_with_ssh_key.is_none()
+        {
+            return Ok(self.repository.commit(
+                Some("HEAD"),
+                author,
+                committer,
+                message,
+                tree,
+                &[&commit],
+            )?);
+        }
+
+        // the signed commit only becomes reachable once HEAD is moved to it,
+        // so a failed signature leaves nothing behind
+        let buffer = self.repository.commit_create_buffer(
+            author,
+            committer,
+            message,
+            tree,
+            &[&commit],
+        )?;
+        let buffer = buffer.as_str().ok_or("Commit is not valid UTF-8")?;
+        let config = self.repository.config()?;
+        let signature =
+            match (&self.args.sign_with_ssh_key, &self.args.gpg_sign) {
+                (Some(key), _) => signing::ssh_sign(
+                    &config,
+                    key,
+                    self.args.auth.ssh_key_passphrase_file(),
+                    buffer,
+                ),
+                (None, key) => signing::gpg_sign(
+                    &config,
+                    optional_value(key).flatten(),
+                    buffer,
+                ),
+            }
+            .map_err(|e| format!("Could not sign the commit: {}", e))?;
+        let id = self.repository.commit_signed(buffer, &signature, None)?;
+        self.repository.head()?.set_target(id, "Commit (signed)")?;
+        Ok(id)
+    }
+
+    /// Points the submodule at `path` back to the commit it pointed to
+    /// before its last update. Refuses if the last commit of the checked out
+    /// branch changed anything but submodules, to not revert unrelated work.
+    /// Returns the commit the submodule points to again.
+    pub(crate) fn revert_submodule_update(&self, path: &str) -> Result<Oid> {
+        let head = self.repository.head()?.peel_to_commit()?;
+        if !self.is_submodule_update(&head)? {
+            return Err(format!(
+                "The last commit {} does not only update submodules, \
+                refusing to roll back",
+                head.id()
+            )
+            .into());
+        }
+
+        let path = Path::new(path);
+        let pinned = |commit: &Commit| -> Result<Option<Oid>> {
+            Ok(commit.tree()?.get_path(path).ok().map(|x| x.id()))
+        };
+        let current = pinned(&head)?.ok_or_else(|| {
+            PipelineError::SubmoduleNotFound(format!(
+                "No submodule at {}",
+                path.display()
+            ))
+        })?;
+        // the most recent commit that changed the submodule, following the
+        // first parents like the history of the branch
+        let mut commit = head.clone();
+        let (update, previous) = loop {
+            let parent = commit.parent(0).map_err(|_| {
+                format!("Submodule {} was never updated", path.display())
+            })?;
+            match pinned(&parent)? {
+                Some(previous) if previous == current => commit = parent,
+                Some(previous) => break (commit, previous),
+                None => {
+                    return Err(format!(
+                        "Submodule {} was added in {}, there is nothing to \
+                        roll back to",
+                        path.display(),
+                        commit.id()
+                    )
+                    .into())
+                }
+            }
+        };
+        info!(
+            submodule:% = path.display(), phase = "rollback";
+            "Rolling back {} from {} to {}, updated in {}",
+            path.display(),
+            current,
+            previous,
+            update.id()
+        );
+
+        let mut index = self.repository.index()?;
+        let mut entry = index
+            .get_path(path, 0)
+            .ok_or_else(|| format!("{} is not in the index", path.display()))?;
+        entry.id = previous;
+        index.add(&entry)?;
+        index.write()?;
+        let tree = self.repository.find_tree(index.write_tree()?)?;
+
+        let message = format!(
+            "Roll back submodule {} to {}\n\nThis reverts the update to {} \
+            in commit {}.",
+            path.display(),
+            previous,
+            current,
+            update.id()
+        );
+        let inherited = self
+            .repository
+            .signature()
+            .unwrap_or_else(|_| head.committer().to_owned());
+        let author = signature(
+            &self.args.author_name,
+            &self.args.author_email,
+            inherited.clone(),
+            self.args.preserve_timestamps,
+        )?;
+        let committer = signature(
+            &self.args.committer_name,
+            &self.args.committer_email,
+            inherited,
+            self.args.preserve_timestamps,
+        )?;
+        self.commit_tree(&author, &committer, &message, &tree)?;
+        Ok(previous)
+    }
+
+    /// Whether `commit` has a single parent and only changed submodules
+    fn is_submodule_update(&self, commit: &Commit) -> Result<bool> {
+        if commit.parent_count() != 1 {
+            return Ok(false);
+        }
+        let diff = self.repository.diff_tree_to_tree(
+            Some(&commit.parent(0)?.tree()?),
+            Some(&commit.tree()?),
+            None,
+        )?;
+        Ok(diff.deltas().len() > 0
+            && diff.deltas().all(|x| {
+                x.old_file().mode() == FileMode::Commit
+                    && x.new_file().mode() == FileMode::Commit
+            }))
+    }
+
+    /// The branch of the composite repository to update, from
+    /// `--target-branch` or the ref of the child repository
+    pub(crate) fn target_ref_of_child(&self) -> Result<String> {
+        if let Some(target_branch) = &self.args.target_branch {
+            return Ok(format!("refs/heads/{}", target_branch));
+        }
+        let child_repository = Self::open(&self.args.repository, self.args)?;
+        self.target_ref(&child_repository.git_ref()?)
+    }
+
+    fn get_branch_name_from_ref(git_ref: &str) -> Result<&str> {
+        let prefix = "refs/heads/";
+        if let Some(branch_name) = git_ref.strip_prefix(prefix) {
+            Ok(branch_name)
+        } else {
+            Err(PipelineError::InvalidGitRef(git_ref.to_string()))
+        }
+    }
+
+    /// Describes what `push` would do for the confirmation prompt
+    fn push_summary(&self, git_ref_target: &str) -> Result<String> {
+        let branch_name = Self::get_branch_name_from_ref(git_ref_target)?;
+        let commit = self.repository.head()?.peel_to_commit()?;
+        Ok(format!(
+            "{} of {}: {} {}",
+            branch_name,
+            redact::urls(&self.push_url()?),
+            &commit.id().to_string()[..10],
+            commit.summary().unwrap_or_default()
+        ))
+    }
+
+    /// The commit `branch_name` points to on `url` right now, fetched to a
+    /// separate reference to leave the remote-tracking branch alone
+    fn remote_tip(&self, url: &str, branch_name: &str) -> Result<Option<Oid>> {
+        let tip_ref = format!("refs/deployment/tip/{}", branch_name);
+        let mut remote = self.repository.remote_anonymous(url)?;
+        let mut options = Self::fetch_options(self.args, url, self.kind)?;
+        remote
+            .fetch(
+                &[&format!("+refs/heads/{}:{}", branch_name, tip_ref)],
+                Some(&mut options),
+                None,
+            )
+            .map_err(|e| Self::explain(self.args, e))?;
+        let tip = match self.repository.find_reference(&tip_ref) {
+            Ok(mut reference) => {
+                let tip = reference.peel_to_commit()?.id();
+                reference.delete()?;
+                Some(tip)
+            }
+            Err(e) if e.code() == ErrorCode::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(tip)
+    }
+
+    /// Fails if the target branch moved since the clone, which a push would
+    /// report as a non-fast-forward. `--force` overwrites it anyway.
+    fn check_remote_unchanged(
+        &self,
+        url: &str,
+        branch_name: &str,
+    ) -> Result<()> {
+        let base = match self.repository.find_reference(&format!(
+            "refs/remotes/{}/{}",
+            self.remote, branch_name
+        )) {
+            Ok(reference) => Some(reference.peel_to_commit()?.id()),
+            Err(e) if e.code() == ErrorCode::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let tip = self.remote_tip(url, branch_name)?;
+        let describe = |id: Option<Oid>| {
+            id.map_or("nothing".to_string(), |x| x.to_string())
+        };
+        trace!(
+            "{} was at {} when cloned and is at {} now",
+            branch_name,
+            describe(base),
+            describe(tip)
+        );
+        if let Some(expected) = self
+            .args
+            .expect_remote
+            .as_ref()
+            .filter(|_| self.kind == RemoteKind::Composite)
+        {
+            if !tip.is_some_and(|x| x.to_string().starts_with(expected)) {
+                return Err(PipelineError::PushRejected(format!(
+                    "Branch {} of {} is at {}, expected {} (--expect-remote)",
+                    branch_name,
+                    redact::urls(url),
+                    describe(tip),
+                    expected
+                )));
+            }
+        }
+        if tip != base && !self.args.force {
+            return Err(PipelineError::Conflict(format!(
+                "Branch {} of {} moved from {} to {} while updating it, \
+                re-run to update the new commit",
+                branch_name,
+                redact::urls(url),
+                describe(base),
+                describe(tip)
+            )));
+        }
+        Ok(())
+    }
+
+    fn push(&self, git_ref_target: &str) -> Result<()> {
+        let branch_name = Self::get_branch_name_from_ref(git_ref_target)?;
+        let url = self.push_url()?;
+        let mut remote = self.repository.remote_anonymous(&url)?;
+
+        // https://docs.rs/git2/latest/git2/struct.RemoteCallbacks.html
+        // git -c http.https://<url of submodule repository>.extraheader="AUTHORIZATION: basic <BASE64_ENCODED_TOKEN_DESCRIBED_ABOVE>" submodule update --init --recursive
+        // https://learn.microsoft.com/en-us/azure/devops/pipelines/repos/pipeline-options-for-git?view=azure-devops&tabs=yaml#alternative-to-using-the-checkout-submodules-option
+        let head = self.repository.head()?;
+        if !head.is_branch() {
+            return Err(PipelineError::NotABranch(
+                "Composite repository is not on a branch".to_string(),
+            ));
+        }
+
+        let force = if self.args.force { "+" } else { "" };
+        let refspec = format!(
+            "{}refs/heads/{}:refs/heads/{}",
+            force,
+            self.args.temp_branch(),
+            branch_name
+        );
+        self.check_remote_unchanged(&url, branch_name)?;
+        if self.args.dry_run {
+            let commit = head.peel_to_commit()?;
+            self.args.print(&format!(
+                "[dry-run] Would push {} to {} of {}",
+                commit.id(),
+                branch_name,
+                redact::urls(&url)
+            ));
+            self.args.print(&format!(
+                "[dry-run] Commit message:\n{}",
+                commit.message().unwrap_or_default().trim_end()
+            ));
+            info!("[dry-run] Not pushing {}", refspec);
+            return Ok(());
+        }
+
+        if self.args.force {
+            self.args.print_styled(
+                Style::Warning,
+                &format!(
+                    "Warning: Force pushing to {}, commits on it that are not \
+                    part of the update are lost",
+                    branch_name
+                ),
+            );
+        }
+        self.args
+            .print_styled(Style::Step, &format!("Pushing to {}", branch_name));
+        let attempts = self.args.push_retries + 1;
+        for attempt in 1.. {
+            if attempts > 1 {
+                info!(
+                    repository:% = redact::urls(&url), phase = "push";
+                    "Push attempt {}/{}",
+                    attempt,
+                    attempts
+                );
+            }
+            let mut options = self.push_options(&url)?;
+            let error = match remote.push(&[&refspec], Some(&mut options)) {
+                Ok(()) => break,
+                Err(error) => error,
+            };
+            if attempt < attempts
+                && is_transient(&error)
+                && !timeout::overall_expired()
+            {
+                let delay = backoff(
+                    Duration::from_millis(self.args.push_retry_delay),
+                    attempt,
+                    MAX_PUSH_RETRY_DELAY,
+                );
+                self.args.print_styled(
+                    Style::Warning,
+                    &format!(
+                        "Warning: Push attempt {}/{} failed, retrying in \
+                        {}ms: {}",
+                        attempt,
+                        attempts,
+                        delay.as_millis(),
+                        error.message()
+                    ),
+                );
+                thread::sleep(delay);
+                continue;
+            }
+            let error = self.explain_push_error(error, branch_name);
+            return Err(if attempts > 1 {
+                format!("{} (push attempt {}/{})", error, attempt, attempts)
+                    .into()
+            } else {
+                error
+            });
+        }
+        Ok(())
+    }
+
+    fn push_options(&self, url: &str) -> Result<PushOptions<'a>> {
+        let mut options = PushOptions::new();
+        let headers = self.args.push_custom_headers(url, self.kind);
+        trace_headers(&headers, url);
+        options.custom_headers(&str_refs(&headers));
+        let mut callbacks = auth::push_remote_callbacks(&self.args.auth);
+        self.args.tls.install(&mut callbacks)?;
+        // rejections by the server do not fail the push by themselves
+        callbacks.push_update_reference(|refname, status| match status {
+            None => Ok(()),
+            Some(status) => Err(Self::push_rejected(refname, status)),
+        });
+        let deadline = timeout::deadline(self.args.network_timeout);
+        callbacks.sideband_progress(move |_| {
+            deadline.is_none_or(|x| Instant::now() < x)
+        });
+        callbacks.push_negotiation(move |_| {
+            if deadline.is_some_and(|x| Instant::now() >= x) {
+                return Err(git2::Error::new(
+                    ErrorCode::User,
+                    ErrorClass::Callback,
+                    "Network timeout exceeded",
+                ));
+            }
+            Ok(())
+        });
+        options.remote_callbacks(callbacks);
+        options.proxy_options(self.args.proxy.proxy_options(url));
+        Ok(options)
+    }
+
+    fn push_rejected(refname: &str, status: &str) -> git2::Error {
+        let message = format!("Pushing {} was rejected: {}", refname, status);
+        if status.contains("non-fast-forward") || status.contains("fetch first")
+        {
+            git2::Error::new(
+                ErrorCode::NotFastForward,
+                ErrorClass::Reference,
+                message,
+            )
+        } else {
+            git2::Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Reference,
+                message,
+            )
+        }
+    }
+
+    fn explain_push_error(
+        &self,
+        error: git2::Error,
+        branch_name: &str,
+    ) -> Error {
+        if error.code() == ErrorCode::NotFastForward {
+            PipelineError::Conflict(format!(
+                "Cannot fast-forward {} in the composite repository, re-run \
+                with --retry-on-conflict to update it again or with --force \
+                to overwrite it: {}",
+                branch_name,
+                error.message()
+            ))
+        } else if error.class() == ErrorClass::Reference {
+            PipelineError::PushRejected(error.message().to_string())
+        } else {
+            let phase = format!("Pushing to {}", branch_name);
+            Self::explain_timeout(self.args, &phase, error)
+        }
+    }
+}
+
+/// Opens the child repository at `path` and resolves its ref and the commit
+/// to update to. The signature is checked before anything is cloned.
+fn open_child<'a>(
+    path: &str,
+    args: &'a Args,
+) -> Result<(RepositoryWrapper<'a>, String, Oid)> {
+    let child_repository = RepositoryWrapper::open(path, args)?;
+    let git_ref = child_repository.git_ref()?;
+    let child_head_oid = child_repository.head_id(&git_ref)?;
+    if args.require_signed_head {
+        signing::verify_commit(
+            &child_repository.repository,
+            child_head_oid,
+            args.allowed_signers.as_deref(),
+        )?;
+        info!("Signature of {} verified", child_head_oid);
+    }
+    Ok((child_repository, git_ref, child_head_oid))
+}
+
+/// The value of an option with an optional value like `--gpg-sign`. The
+/// empty value of an environment variable stands for the option without one.
+fn optional_value(value: &Option<Option<String>>) -> Option<Option<&str>> {
+    value
+        .as_ref()
+        .map(|x| x.as_deref().filter(|x| !x.is_empty()))
+}
+
+/// The name of a `refs/tags/<tag>` ref
+fn tag_name(git_ref: &str) -> Option<String> {
+    git_ref.strip_prefix("refs/tags/").map(|x| x.to_string())
+}
+
+/// Points the submodules that contain `id` to it, or a nested submodule with
+/// `--recursive`. Returns the paths of the submodules and the nested
+/// repositories that have to be pushed before the composite repository.
+fn update_child<'a>(
+    composite_repo: &RepositoryWrapper<'a>,
+    id: Oid,
+    git_ref: &str,
+) -> Result<(Vec<String>, Vec<RepositoryWrapper<'a>>)> {
+    match composite_repo.find_submodules(id) {
+        Ok(mut submodules) => {
+            let found_paths = submodules
+                .iter()
+                .map(|x| x.path().display().to_string())
+                .collect();
+            composite_repo.update_submodules_to_id(&mut submodules, id)?;
+            Ok((found_paths, vec![]))
+        }
+        Err(e) if composite_repo.args.recursive => {
+            trace!("{}, searching nested submodules", e);
+            let nested = composite_repo.update_nested_submodule(id, git_ref)?;
+            Ok((vec![], nested))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Describes the update of the composite repository to `child_head_oid`
+/// since `base`
+fn outcome(
+    composite_repo: &RepositoryWrapper,
+    child_repository: &RepositoryWrapper,
+    child_ref: String,
+    child_head_oid: Oid,
+    git_ref: &str,
+    base: Oid,
+    found_paths: &[String],
+) -> Result<PipelineOutcome> {
+    let changed = composite_repo.changed_submodules(base)?;
+    let mut outcome = PipelineOutcome {
+        child_repository: child_repository
+            .repository
+            .workdir()
+            .unwrap_or_else(|| child_repository.repository.path())
+            .display()
+            .to_string(),
+        git_ref: child_ref,
+        composite_repository: redact::urls(
+            composite_repo.args.composite_repository(),
+        ),
+        submodule_path: None,
+        submodules: Vec::new(),
+        previous_commit: None,
+        new_commit: child_head_oid.to_string(),
+        composite_commit: composite_repo.head_commit()?.to_string(),
+        target_branch: RepositoryWrapper::get_branch_name_from_ref(git_ref)?
+            .to_string(),
+        updated: false,
+        pushed: false,
+    };
+    if changed.is_empty() {
+        if !found_paths.is_empty() {
+            outcome.submodule_path = Some(found_paths.join(", "));
+            outcome.previous_commit = Some(child_head_oid.to_string());
+        }
+    } else {
+        let paths: Vec<_> = changed.iter().map(|x| x.path.as_str()).collect();
+        outcome.submodule_path = Some(paths.join(", "));
+        outcome.previous_commit = Some(changed[0].previous_commit.clone());
+        outcome.submodules = changed;
+    }
+    Ok(outcome)
+}
+
+/// Asks before pushing on a terminal, then pushes the nested repositories
+/// and the composite repository. With --no-push the update is written to
+/// --patch-file instead.
+pub(crate) fn push_all(
+    args: &Args,
+    nested: Vec<RepositoryWrapper>,
+    composite_repo: &RepositoryWrapper,
+    git_ref: &str,
+) -> Result<()> {
+    if args.no_push {
+        return composite_repo.write_patch(git_ref);
+    }
+    if !args.dry_run && !args.yes && is_interactive() {
+        let mut pushes = Vec::new();
+        for repository in nested.iter().chain([composite_repo]) {
+            pushes.push(repository.push_summary(git_ref)?);
+        }
+        confirm_push(&pushes)?;
+    }
+
+    // the nested commits have to exist before the composite repository can
+    // point to them
+    for nested in nested {
+        nested.push(git_ref)?;
+    }
+    composite_repo.push(git_ref)
+}
+
+/// Runs a pipeline from other Rust code, like the `pipeline` subcommand.
+/// Messages go to stderr unless `quiet` is set, errors are returned.
+///
+/// ```no_run
+/// use clap::Parser;
+/// use deployment_withlazers::subcommands::pipeline::Args;
+/// use deployment_withlazers::Pipeline;
+///
+/// let args = Args::parse_from([
+///     "pipeline",
+///     "--repository",
+///     "service",
+///     "--composite-repository",
+///     "https://example.com/composite.git",
+/// ]);
+/// let outcome = Pipeline::new(args).run()?;
+/// println!("{} pushed: {}", outcome.composite_commit, outcome.pushed);
+/// # Ok::<(), deployment_withlazers::PipelineError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    args: Args,
+}
+
+impl Pipeline {
+    pub fn new(args: Args) -> Self {
+        Self { args }
+    }
+
+    /// Updates the composite repository to the HEAD of the child
+    /// repository and pushes it
+    pub fn run(self) -> Result<PipelineOutcome> {
+        if self.args.repositories_file.is_some() {
+            return Err(PipelineError::InvalidArguments(
+                "--repositories-file is run with Pipeline::run_batch"
+                    .to_string(),
+            ));
+        }
+        if self.args.composite_repositories.len() > 1 {
+            return Err(PipelineError::InvalidArguments(
+                "Several --composite-repository are run with \
+                Pipeline::run_composites"
+                    .to_string(),
+            ));
+        }
+        run(self.args)
+    }
+
+    /// Updates each `--composite-repository` in a clone of its own and
+    /// pushes it
+    pub fn run_composites(self) -> Result<CompositesOutcome> {
+        run_composites(self.args)
+    }
+
+    /// Updates all child repositories of `--repositories-file` and pushes
+    /// them together
+    pub fn run_batch(self) -> Result<BatchOutcome> {
+        run_batch(self.args)
+    }
+}
+
+fn run(mut args: Args) -> Result<PipelineOutcome> {
+    args.load_files()?;
+    args.check_outputs()?;
+    args.check_force()?;
+    if args.lfs {
+        lfs::check_installed()?;
+    }
+    let (child_repository, child_ref, child_head_oid) =
+        open_child(&args.repository, &args)?;
+
+    let branch = args.clone_branch(&child_ref)?;
+    let mut composite_repo =
+        RepositoryWrapper::open_composite(&args, branch.as_deref())?;
+    composite_repo.set_child(&child_repository, &child_ref);
+
+    let git_ref = composite_repo.target_ref(&child_ref)?;
+    composite_repo.checkout_temp_branch(&git_ref)?;
+
+    let attempts = args.retry_on_conflict + 1;
+    let mut attempt = 1;
+    loop {
+        let base_oid = composite_repo.head_commit()?;
+        let (found_paths, nested) =
+            update_child(&composite_repo, child_head_oid, &git_ref)?;
+        let mut outcome = outcome(
+            &composite_repo,
+            &child_repository,
+            child_ref.clone(),
+            child_head_oid,
+            &git_ref,
+            base_oid,
+            &found_paths,
+        )?;
+
+        if composite_repo.head_commit()? == base_oid {
+            args.print_styled(Style::Success, "Already up to date");
+            args.write_outputs(&outcome)?;
+            return Ok(outcome);
+        }
+
+        match push_all(&args, nested, &composite_repo, &git_ref) {
+            Ok(()) => {
+                for update in &outcome.submodules {
+                    args.print(&format!(
+                        "Updated {} from {} to {}",
+                        update.path,
+                        &update.previous_commit[..10],
+                        &update.new_commit[..10]
+                    ));
+                }
+                outcome.updated = true;
+                outcome.pushed = !args.dry_run && !args.no_push;
+                if outcome.pushed {
+                    args.notify_webhook(&outcome)?;
+                }
+                args.write_outputs(&outcome)?;
+                return Ok(outcome);
+            }
+            Err(PipelineError::Conflict(message)) if attempt < attempts => {
+                let delay = backoff(
+                    CONFLICT_RETRY_DELAY,
+                    attempt,
+                    MAX_CONFLICT_RETRY_DELAY,
+                );
+                info!(phase = "push"; "{}", message);
+                args.print_styled(
+                    Style::Warning,
+                    &format!(
+                        "Warning: The target branch moved, updating it again \
+                        in {}ms (attempt {}/{})",
+                        delay.as_millis(),
+                        attempt + 1,
+                        attempts
+                    ),
+                );
+                thread::sleep(delay);
+                composite_repo.reset_temp_branch(&git_ref)?;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The paths listed in `--repositories-file`, relative ones are relative to
+/// the file
+fn read_repositories_file(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        PipelineError::InvalidArguments(format!("{}: {}", path.display(), e))
+    })?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let paths: Vec<_> = content
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .map(|x| directory.join(x).display().to_string())
+        .collect();
+    if paths.is_empty() {
+        return Err(PipelineError::InvalidArguments(format!(
+            "{} lists no repositories",
+            path.display()
+        )));
+    }
+    Ok(paths)
+}
+
+/// Runs the pipeline for each `--composite-repository` in turn and reports
+/// how each of them went at the end
+fn run_composites(args: Args) -> Result<CompositesOutcome> {
+    args.check_composites()?;
+    let mut composites = Vec::new();
+    let mut error = None;
+    for url in &args.composite_repositories {
+        let mut composite_args = args.clone();
+        composite_args.composite_repositories = vec![url.clone()];
+        let url = redact::urls(url);
+        args.print_styled(Style::Step, &format!("Updating {}", url));
+        match run(composite_args) {
+            Ok(outcome) => composites.push(CompositeOutcome::Updated(outcome)),
+            Err(e) => {
+                composites.push(CompositeOutcome::Failed {
+                    composite_repository: url,
+                    error: redact::urls(&e.to_string()),
+                    exit_code: e.exit_code(),
+                });
+                if !args.keep_going {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+    }
+
+    for composite in &composites {
+        match composite {
+            CompositeOutcome::Updated(outcome) => args.print_styled(
+                Style::Success,
+                &format!(
+                    "OK    {}  {}",
+                    outcome.composite_repository,
+                    if outcome.updated {
+                        &outcome.composite_commit
+                    } else {
+                        "already up to date"
+                    }
+                ),
+            ),
+            CompositeOutcome::Failed {
+                composite_repository,
+                error,
+                ..
+            } => output::message(
+                Style::Error,
+                &format!("FAIL  {}: {}", composite_repository, error),
+            ),
+        }
+    }
+    if let Some(error) = error {
+        return Err(error);
+    }
+    let updated = composites.iter().any(
+        |x| matches!(x, CompositeOutcome::Updated(outcome) if outcome.updated),
+    );
+    Ok(CompositesOutcome {
+        composites,
+        updated,
+    })
+}
+
+/// Updates the child repositories of `--repositories-file` in one clone of
+/// the composite repository and pushes all commits together. They have to
+/// map to the same branch of the composite repository.
+fn run_batch(mut args: Args) -> Result<BatchOutcome> {
+    args.check_composites()?;
+    args.load_files()?;
+    args.check_outputs()?;
+    args.check_force()?;
+    if args.lfs {
+        lfs::check_installed()?;
+    }
+    let paths = match &args.repositories_file {
+        Some(path) => read_repositories_file(path)?,
+        None => return Err("--repositories-file is required".into()),
+    };
+    let mut results: Vec<Option<ChildOutcome>> =
+        paths.iter().map(|_| None).collect();
+    // stops the run unless --keep-going is given
+    let fail = |results: &mut Vec<_>, index: usize, error: Error| {
+        if !args.keep_going {
+            output::message(Style::Error, &format!("{} failed", paths[index]));
+            return Err(error);
+        }
+        let message = redact::urls(&error.to_string());
+        output::message(
+            Style::Error,
+            &format!("{} failed: {}", paths[index], message),
+        );
+        results[index] = Some(ChildOutcome::Failed {
+            child_repository: paths[index].clone(),
+            error: message,
+            exit_code: error.exit_code(),
+        });
+        Ok(())
+    };
+
+    let mut children = Vec::new();
+    for (index, path) in paths.iter().enumerate() {
+        match open_child(path, &args) {
+            Ok(child) => children.push((index, child)),
+            Err(e) => fail(&mut results, index, e)?,
+        }
+    }
+    if children.is_empty() {
+        return Err("None of the child repositories could be opened".into());
+    }
+
+    let mut composite_repo = RepositoryWrapper::open_composite(&args, None)?;
+
+    // refs of tags resolve to the default branch, which has to be looked up
+    // before the temporary branch is checked out
+    let mut git_ref = None;
+    let mut updates = Vec::new();
+    for (index, (child_repository, child_ref, id)) in children {
+        let target_ref = composite_repo.target_ref(&child_ref)?;
+        let git_ref = git_ref.get_or_insert_with(|| target_ref.clone());
+        if target_ref != *git_ref {
+            let error = PipelineError::InvalidArguments(format!(
+                "{} maps to {}, the other repositories to {}",
+                paths[index], target_ref, git_ref
+            ));
+            fail(&mut results, index, error)?;
+            continue;
+        }
+        updates.push((index, child_repository, child_ref, id));
+    }
+    let git_ref = git_ref.ok_or("No child repository to update")?;
+    composite_repo.checkout_temp_branch(&git_ref)?;
+    let base_oid = composite_repo.head_commit()?;
+
+    for (index, child_repository, child_ref, id) in updates {
+        let before = composite_repo.head_commit()?;
+        composite_repo.set_child(&child_repository, &child_ref);
+        let result = update_child(&composite_repo, id, &git_ref).and_then(
+            |(found_paths, _)| {
+                let mut outcome = outcome(
+                    &composite_repo,
+                    &child_repository,
+                    child_ref,
+                    id,
+                    &git_ref,
+                    before,
+                    &found_paths,
+                )?;
+                outcome.updated = composite_repo.head_commit()? != before;
+                Ok(outcome)
+            },
+        );
+        match result {
+            Ok(outcome) => {
+                let submodule_path =
+                    outcome.submodule_path.as_deref().unwrap_or_default();
+                args.print(&if outcome.updated {
+                    format!(
+                        "{}: updated {} to {}",
+                        paths[index], submodule_path, id
+                    )
+                } else {
+                    format!(
+                        "{}: {} is up to date",
+                        paths[index], submodule_path
+                    )
+                });
+                results[index] = Some(ChildOutcome::Updated(outcome));
+            }
+            Err(e) => {
+                // drops the commits of a partial update
+                let before =
+                    composite_repo.repository.find_object(before, None)?;
+                composite_repo.repository.reset(
+                    &before,
+                    ResetType::Hard,
+                    None,
+                )?;
+                fail(&mut results, index, e)?;
+            }
+        }
+    }
+
+    let updated = composite_repo.head_commit()? != base_oid;
+    if updated {
+        push_all(&args, vec![], &composite_repo, &git_ref)?;
+    } else {
+        args.print_styled(Style::Success, "Already up to date");
+    }
+    let pushed = updated && !args.dry_run && !args.no_push;
+    let children: Vec<_> = results
+        .into_iter()
+        .flatten()
+        .map(|mut x| {
+            if let ChildOutcome::Updated(outcome) = &mut x {
+                outcome.pushed = pushed && outcome.updated;
+            }
+            x
+        })
+        .collect();
+    for child in &children {
+        match child {
+            ChildOutcome::Updated(outcome) if outcome.pushed => {
+                args.notify_webhook(outcome)?
+            }
+            _ => {}
+        }
+    }
+    let outcome = BatchOutcome {
+        children,
+        composite_repository: redact::urls(args.composite_repository()),
+        composite_commit: composite_repo.head_commit()?.to_string(),
+        target_branch: RepositoryWrapper::get_branch_name_from_ref(&git_ref)?
+            .to_string(),
+        updated,
+        pushed,
+    };
+    args.write_composite_commit(&outcome.composite_commit)?;
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that change environment variables must not run in parallel
+    static ENV: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn environment_variables_fill_in_missing_options() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let variables = [
+            ("DEPLOYMENT_REPOSITORY", "/from/env"),
+            ("DEPLOYMENT_GIT_REF", "refs/heads/main"),
+            ("DEPLOYMENT_COMPOSITE_REPOSITORY", "/a\n/b"),
+            ("DEPLOYMENT_CUSTOM_HEADERS", "A: 1\nB: 2"),
+            ("DEPLOYMENT_GPG_SIGN", ""),
+            ("DEPLOYMENT_BRANCH_MAP", "main=staging"),
+        ];
+        for (name, value) in variables {
+            env::set_var(name, value);
+        }
+
+        let args = Args::try_parse_from(["pipeline"]);
+        let cli = Args::try_parse_from([
+            "pipeline",
+            "-r",
+            "/from/cli",
+            "-c",
+            "/c",
+            "--custom-headers",
+            "C: 3",
+            "--gpg-sign=KEY",
+        ]);
+        for (name, _) in variables {
+            env::remove_var(name);
+        }
+
+        let args = args.unwrap();
+        assert_eq!(args.repository, "/from/env");
+        assert_eq!(args.git_ref.as_deref(), Some("refs/heads/main"));
+        assert_eq!(args.composite_repositories, ["/a", "/b"]);
+        assert_eq!(args.custom_headers, ["A: 1", "B: 2"]);
+        assert_eq!(optional_value(&args.gpg_sign), Some(None));
+        assert_eq!(
+            args.branch_map,
+            [(
+                "refs/heads/main".to_string(),
+                "refs/heads/staging".to_string()
+            )]
+        );
+
+        let cli = cli.unwrap();
+        assert_eq!(cli.repository, "/from/cli");
+        assert_eq!(cli.git_ref.as_deref(), Some("refs/heads/main"));
+        assert_eq!(cli.composite_repositories, ["/c"]);
+        assert_eq!(cli.custom_headers, ["C: 3"]);
+        assert_eq!(optional_value(&cli.gpg_sign), Some(Some("KEY")));
+    }
+
+    /// Options without environment variables that change them
+    fn default_args() -> Args {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        Args::try_parse_from(["pipeline", "-c", "/c"]).unwrap()
+    }
+
+    /// A repository with the branches `main` and `feature/x`, the tag
+    /// `v1.0` and the remote-tracking branch `origin/remote-only`
+    fn repository(path: &Path) -> Repository {
+        let repository = Repository::init(path).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let tree_id = repository.index().unwrap().write_tree().unwrap();
+        let tree = repository.find_tree(tree_id).unwrap();
+        let id = repository
+            .commit(None, &signature, &signature, "Initial", &tree, &[])
+            .unwrap();
+        for name in [
+            "refs/heads/main",
+            "refs/heads/feature/x",
+            "refs/tags/v1.0",
+            "refs/remotes/origin/main",
+            "refs/remotes/origin/remote-only",
+        ] {
+            repository.reference(name, id, true, "test").unwrap();
+        }
+        repository.remote("origin", "/nowhere").unwrap();
+        drop(tree);
+        repository
+    }
+
+    #[test]
+    fn full_ref_accepts_short_and_remote_names() {
+        let directory = tempdir().unwrap();
+        repository(directory.path());
+        let args = default_args();
+        let wrapper =
+            RepositoryWrapper::open(directory.path().to_str().unwrap(), &args)
+                .unwrap();
+        let full_ref = |x| wrapper.full_ref(x).unwrap();
+        assert_eq!(full_ref("refs/heads/main"), "refs/heads/main");
+        assert_eq!(full_ref("refs/tags/v1.0"), "refs/tags/v1.0");
+        assert_eq!(full_ref("main"), "refs/heads/main");
+        assert_eq!(full_ref("feature/x"), "refs/heads/feature/x");
+        assert_eq!(full_ref("origin/main"), "refs/heads/main");
+        assert_eq!(full_ref("refs/remotes/origin/main"), "refs/heads/main");
+        assert_eq!(full_ref("remote-only"), "refs/heads/remote-only");
+        assert_eq!(full_ref("v1.0"), "refs/tags/v1.0");
+        // unknown names are branches that do not exist yet
+        assert_eq!(full_ref("new"), "refs/heads/new");
+    }
+
+    #[test]
+    fn full_ref_rejects_invalid_refs() {
+        let directory = tempdir().unwrap();
+        repository(directory.path());
+        let args = default_args();
+        let wrapper =
+            RepositoryWrapper::open(directory.path().to_str().unwrap(), &args)
+                .unwrap();
+        for git_ref in [
+            "",
+            "refs/pull/1/head",
+            "refs/remotes/origin",
+            "main..other",
+            "refs/heads/with space",
+        ] {
+            assert!(
+                matches!(
+                    wrapper.full_ref(git_ref),
+                    Err(PipelineError::InvalidGitRef(_))
+                ),
+                "{:?} was accepted",
+                git_ref
+            );
+        }
+    }
+
+    #[test]
+    fn branch_name_of_full_refs() {
+        assert_eq!(
+            RepositoryWrapper::get_branch_name_from_ref("refs/heads/a/b")
+                .unwrap(),
+            "a/b"
+        );
+        assert!(
+            RepositoryWrapper::get_branch_name_from_ref("refs/tags/v1.0")
+                .is_err()
+        );
+        assert!(RepositoryWrapper::get_branch_name_from_ref("main").is_err());
+    }
+
+    #[test]
+    fn ci_ref_uses_the_first_branch_or_tag() {
+        let directory = tempdir().unwrap();
+        repository(directory.path());
+        let args = default_args();
+        let wrapper =
+            RepositoryWrapper::open(directory.path().to_str().unwrap(), &args)
+                .unwrap();
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let ci_ref = |variables: &[(&str, &str)]| {
+            let names = ["GITHUB_REF", "GITHUB_REF_TYPE"];
+            for name in CI_REF_VARIABLES.iter().chain(&names) {
+                env::remove_var(name);
+            }
+            for (name, value) in variables {
+                env::set_var(name, value);
+            }
+            let result = wrapper.ci_ref().unwrap();
+            for (name, _) in variables {
+                env::remove_var(name);
+            }
+            result
+        };
+        let found = |variable, git_ref: &str| Some((variable, git_ref.into()));
+
+        assert_eq!(ci_ref(&[]), None);
+        assert_eq!(
+            ci_ref(&[("GITHUB_REF_NAME", "main")]),
+            found("GITHUB_REF_NAME", "refs/heads/main")
+        );
+        assert_eq!(
+            ci_ref(&[("GITHUB_REF_NAME", "v2"), ("GITHUB_REF_TYPE", "tag")]),
+            found("GITHUB_REF_NAME", "refs/tags/v2")
+        );
+        assert_eq!(
+            ci_ref(&[
+                ("GITHUB_REF_NAME", "main"),
+                ("CI_COMMIT_REF_NAME", "feature/x"),
+                ("BUILD_SOURCEBRANCH", "refs/tags/v1.0"),
+            ]),
+            found("GITHUB_REF_NAME", "refs/heads/main")
+        );
+        assert_eq!(
+            ci_ref(&[
+                ("GITHUB_REF_NAME", ""),
+                ("CI_COMMIT_REF_NAME", "feature/x"),
+            ]),
+            found("CI_COMMIT_REF_NAME", "refs/heads/feature/x")
+        );
+        assert_eq!(
+            ci_ref(&[("BUILD_SOURCEBRANCH", "refs/tags/v1.0")]),
+            found("BUILD_SOURCEBRANCH", "refs/tags/v1.0")
+        );
+        // pull requests are skipped
+        assert_eq!(
+            ci_ref(&[
+                ("GITHUB_REF_NAME", "1/merge"),
+                ("GITHUB_REF", "refs/pull/1/merge"),
+                ("BUILD_SOURCEBRANCH", "refs/heads/main"),
+            ]),
+            found("BUILD_SOURCEBRANCH", "refs/heads/main")
+        );
+        assert_eq!(
+            ci_ref(&[("BUILD_SOURCEBRANCH", "refs/pull/3/merge")]),
+            None
+        );
+    }
+
+    #[test]
+    fn trailers_are_key_value_pairs() {
+        assert_eq!(parse_trailer("Build-Id: 42").unwrap(), "Build-Id: 42");
+        assert_eq!(
+            parse_trailer(" Ticket :  OPS-1 ").unwrap(),
+            "Ticket: OPS-1"
+        );
+        assert_eq!(
+            parse_trailer("Link: https://ci/1").unwrap(),
+            "Link: https://ci/1"
+        );
+        for invalid in ["Build-Id", ": 42", "Build Id: 42", "Key:", "A: b\nc"] {
+            assert!(parse_trailer(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn sign_offs_are_names_with_emails() {
+        assert_eq!(parse_sign_off("").unwrap(), "");
+        assert_eq!(
+            parse_sign_off("Jane Doe <jane@example.com>").unwrap(),
+            "Jane Doe <jane@example.com>"
+        );
+        assert_eq!(
+            parse_sign_off("  Jane  < jane@example.com > ").unwrap(),
+            "Jane <jane@example.com>"
+        );
+        for invalid in [
+            "Jane",
+            "jane@example.com",
+            "<jane@example.com>",
+            "Jane <jane>",
+            "Jane <jane@example.com",
+            "Jane <a@b> <c@d>",
+        ] {
+            assert!(parse_sign_off(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let ms = Duration::from_millis;
+        let max = ms(60_000);
+        assert_eq!(backoff(ms(1000), 1, max), ms(1000));
+        assert_eq!(backoff(ms(1000), 2, max), ms(2000));
+        assert_eq!(backoff(ms(1000), 6, max), ms(32_000));
+        assert_eq!(backoff(ms(1000), 7, max), max);
+        assert_eq!(backoff(ms(1000), u32::MAX, max), max);
+        assert_eq!(backoff(ms(u64::MAX), u32::MAX, max), ms(u64::MAX));
+        assert_eq!(backoff(ms(0), 10, max), ms(0));
+    }
+
+    #[test]
+    fn retries_are_limited() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let parse = |retries| {
+            Args::try_parse_from([
+                "pipeline",
+                "-c",
+                "/c",
+                "--push-retries",
+                retries,
+            ])
+        };
+        assert_eq!(parse("20").unwrap().push_retries, 20);
+        assert!(parse("21").is_err());
+        assert!(parse("4294967295").is_err());
+        let conflicts = Args::try_parse_from([
+            "pipeline",
+            "-c",
+            "/c",
+            "--retry-on-conflict",
+            "4294967295",
+        ]);
+        assert!(conflicts.is_err());
+    }
 }
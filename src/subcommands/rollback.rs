@@ -0,0 +1,25 @@
+use super::pipeline::{self, Args, RepositoryWrapper};
+use crate::result::{PipelineError, Result};
+
+/// Points the submodule at `--submodule-path` back to the commit before its
+/// last update and pushes that to the target branch. Returns the new commit
+/// of the composite repository.
+pub fn run(mut args: Args) -> Result<String> {
+    args.load_files()?;
+    args.check_single_composite()?;
+    args.check_force()?;
+    let path = args.submodule_path.clone().ok_or_else(|| {
+        PipelineError::InvalidArguments(
+            "rollback requires --submodule-path".to_string(),
+        )
+    })?;
+
+    let composite_repo = RepositoryWrapper::open_composite(&args, None)?;
+    let git_ref = composite_repo.target_ref_of_child()?;
+    composite_repo.checkout_temp_branch(&git_ref)?;
+
+    let previous = composite_repo.revert_submodule_update(&path)?;
+    args.print(&format!("Rolling back {} to {}", path, previous));
+    pipeline::push_all(&args, vec![], &composite_repo, &git_ref)?;
+    Ok(composite_repo.head_commit()?.to_string())
+}
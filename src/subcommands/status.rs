@@ -0,0 +1,44 @@
+use super::pipeline::{Args, RepositoryWrapper};
+use crate::result::Result;
+
+/// Prints whether the submodules in the composite repository are pinned to
+/// the HEAD of the child repository. Returns false if an update is pending.
+pub fn run(mut args: Args) -> Result<bool> {
+    args.load_files()?;
+    args.check_single_composite()?;
+    let child_repository = RepositoryWrapper::open(&args.repository, &args)?;
+
+    let git_ref = child_repository.git_ref()?;
+    let child_head_oid = child_repository.head_id(&git_ref)?;
+
+    let branch = args.clone_branch(&git_ref)?;
+    let composite_repo =
+        RepositoryWrapper::open_composite(&args, branch.as_deref())?;
+
+    let git_ref = composite_repo.target_ref(&git_ref)?;
+    composite_repo.checkout_temp_branch(&git_ref)?;
+
+    let mut up_to_date = true;
+    for submodule in composite_repo.find_submodules(child_head_oid)? {
+        let pinned_oid = submodule
+            .head_id()
+            .ok_or("Submodule is not pinned to a commit")?;
+
+        if pinned_oid == child_head_oid {
+            args.print(&format!(
+                "{} is up to date at {}",
+                submodule.path().display(),
+                pinned_oid
+            ));
+        } else {
+            args.print(&format!(
+                "{} is pinned to {}, update to {} pending",
+                submodule.path().display(),
+                pinned_oid,
+                child_head_oid
+            ));
+            up_to_date = false;
+        }
+    }
+    Ok(up_to_date)
+}
@@ -0,0 +1,72 @@
+use super::pipeline::{Args, RepositoryWrapper};
+use crate::output::{self, Style};
+use crate::redact;
+use crate::result::Result;
+
+/// Checks the options without cloning or changing anything: the child
+/// repository opens, its ref and commit resolve and the composite repository
+/// can be reached with the given credentials and headers. Prints each check
+/// and returns false if any failed.
+pub fn run(mut args: Args) -> Result<bool> {
+    args.load_files()?;
+    args.check_single_composite()?;
+
+    let child_repository =
+        match RepositoryWrapper::open(&args.repository, &args) {
+            Ok(child_repository) => {
+                report(&args, "repository", &args.repository, &Ok(()));
+                Some(child_repository)
+            }
+            Err(e) => {
+                report(&args, "repository", &args.repository, &Err(e));
+                None
+            }
+        };
+
+    let mut ok = child_repository.is_some();
+    if let Some(child_repository) = &child_repository {
+        match child_repository.git_ref() {
+            Ok(git_ref) => {
+                report(&args, "git ref", &git_ref, &Ok(()));
+                let result = child_repository.head_id(&git_ref);
+                let commit = match &result {
+                    Ok(id) => id.to_string(),
+                    Err(_) => git_ref.clone(),
+                };
+                ok &= result.is_ok();
+                report(&args, "commit", &commit, &result.map(|_| ()));
+            }
+            Err(e) => {
+                ok = false;
+                report(&args, "git ref", "HEAD", &Err(e));
+            }
+        }
+    }
+
+    let url = args.composite_repository();
+    let result = RepositoryWrapper::check_url(url, &args);
+    ok &= result.is_ok();
+    report(&args, "composite", url, &result);
+
+    Ok(ok)
+}
+
+/// Failures are errors and printed even with `--quiet`
+fn report(args: &Args, check: &str, subject: &str, result: &Result<()>) {
+    let subject = redact::urls(subject);
+    match result {
+        Ok(()) => args.print_styled(
+            Style::Success,
+            &format!("OK    {:<10}  {}", check, subject),
+        ),
+        Err(e) => output::message(
+            Style::Error,
+            &format!(
+                "FAIL  {:<10}  {}: {}",
+                check,
+                subject,
+                redact::urls(&e.to_string())
+            ),
+        ),
+    }
+}
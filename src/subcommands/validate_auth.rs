@@ -0,0 +1,57 @@
+use super::pipeline::{Args, RemoteKind, RepositoryWrapper};
+use crate::output::{self, Style};
+use crate::redact;
+use crate::result::Result;
+
+/// Connects to the composite repository, its submodules and the push URL
+/// without changing anything, and prints which of them failed. Returns false
+/// if any connection failed.
+pub fn run(mut args: Args) -> Result<bool> {
+    args.load_files()?;
+    args.check_single_composite()?;
+
+    let composite_repo = match RepositoryWrapper::open_composite(&args, None) {
+        Ok(composite_repo) => {
+            report(&args, "fetch", args.composite_repository(), &Ok(()));
+            composite_repo
+        }
+        Err(e) => {
+            report(&args, "fetch", args.composite_repository(), &Err(e));
+            return Ok(false);
+        }
+    };
+
+    let mut ok = true;
+    for url in composite_repo.submodule_urls()? {
+        let result = composite_repo.check_fetch(&url, RemoteKind::Child);
+        ok &= result.is_ok();
+        report(&args, "fetch", &url, &result);
+    }
+
+    let push_url = composite_repo.push_url()?;
+    let result = composite_repo.check_push(&push_url);
+    ok &= result.is_ok();
+    report(&args, "push", &push_url, &result);
+
+    Ok(ok)
+}
+
+/// Failures are errors and printed even with `--quiet`
+fn report(args: &Args, direction: &str, url: &str, result: &Result<()>) {
+    let url = redact::urls(url);
+    match result {
+        Ok(()) => args.print_styled(
+            Style::Success,
+            &format!("OK    {:<5}  {}", direction, url),
+        ),
+        Err(e) => output::message(
+            Style::Error,
+            &format!(
+                "FAIL  {:<5}  {}: {}",
+                direction,
+                url,
+                redact::urls(&e.to_string())
+            ),
+        ),
+    }
+}
@@ -0,0 +1,50 @@
+use crate::output;
+use crate::result::Result;
+use serde::Serialize;
+
+/// Build of the tool, printed by `version`. The fields of the JSON form are
+/// kept stable.
+#[derive(Serialize, Debug)]
+pub struct Version {
+    pub version: &'static str,
+    /// Commit of the tool's repository, `unknown` if it was not built from
+    /// a checkout
+    pub commit: &'static str,
+    /// RFC 3339 in UTC
+    pub build_timestamp: &'static str,
+    pub libgit2: String,
+}
+
+impl Version {
+    pub fn get() -> Self {
+        let (major, minor, revision) = git2::Version::get().libgit2_version();
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("DEPLOY_COMMIT"),
+            build_timestamp: env!("DEPLOY_BUILD_TIMESTAMP"),
+            libgit2: format!("{}.{}.{}", major, minor, revision),
+        }
+    }
+}
+
+/// Shown by `--version`
+pub fn long_version() -> String {
+    let version = Version::get();
+    format!(
+        "{} (commit {}, built {}, libgit2 {})",
+        version.version,
+        version.commit,
+        version.build_timestamp,
+        version.libgit2
+    )
+}
+
+pub fn run(format: output::Format) -> Result<()> {
+    match format {
+        output::Format::Text => println!("deploy {}", long_version()),
+        output::Format::Json => {
+            println!("{}", serde_json::to_string(&Version::get())?)
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::result::Result;
+use std::ffi::c_int;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+// git2 does not wrap these options of libgit2 1.7 yet, see
+// `git_libgit2_opt_t` in git2/common.h
+const GIT_OPT_SET_SERVER_CONNECT_TIMEOUT: c_int = 39;
+const GIT_OPT_SET_SERVER_TIMEOUT: c_int = 41;
+
+/// The point in time all network operations of the run have to be done by
+static OVERALL_DEADLINE: OnceLock<Instant> = OnceLock::new();
+
+/// Sets how long libgit2 waits for a connection to be established and for
+/// a stalled connection to send or receive data. The timeouts apply to all
+/// HTTP(S) and git:// connections of the process.
+pub fn set_server_timeouts(
+    connect: Option<u64>,
+    network: Option<u64>,
+) -> Result<()> {
+    if let Some(connect) = connect {
+        set_option(GIT_OPT_SET_SERVER_CONNECT_TIMEOUT, connect)?;
+    }
+    if let Some(network) = network {
+        set_option(GIT_OPT_SET_SERVER_TIMEOUT, network)?;
+    }
+    Ok(())
+}
+
+fn set_option(option: c_int, seconds: u64) -> Result<()> {
+    let milliseconds = c_int::try_from(seconds * 1000)
+        .map_err(|_| format!("Timeout of {}s is too long", seconds))?;
+    libgit2_sys::init();
+    // SAFETY: both options take a single int argument
+    let result = unsafe { libgit2_sys::git_libgit2_opts(option, milliseconds) };
+    if result < 0 {
+        return Err(git2::Error::last_error(result)
+            .map_or_else(|| "Could not set the timeout".into(), Into::into));
+    }
+    Ok(())
+}
+
+/// Starts the `--timeout` budget shared by all network operations, later
+/// calls keep the first deadline
+pub fn start_overall(seconds: Option<u64>) {
+    if let Some(seconds) = seconds {
+        OVERALL_DEADLINE
+            .get_or_init(|| Instant::now() + Duration::from_secs(seconds));
+    }
+}
+
+/// Whether the `--timeout` budget is used up
+pub fn overall_expired() -> bool {
+    OVERALL_DEADLINE.get().is_some_and(|x| Instant::now() >= *x)
+}
+
+/// The point in time a network operation started now has to be done by,
+/// which is never after the `--timeout` of the whole run
+pub fn deadline(seconds: Option<u64>) -> Option<Instant> {
+    let deadline =
+        seconds.map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    match (deadline, OVERALL_DEADLINE.get()) {
+        (Some(deadline), Some(overall)) => Some(deadline.min(*overall)),
+        (deadline, overall) => deadline.or(overall.copied()),
+    }
+}
+
+/// Whether a failed network operation was aborted because it took too long,
+/// either by libgit2 or by a callback noticing the deadline passed
+pub fn is_timeout(error: &git2::Error) -> bool {
+    error.code() == git2::ErrorCode::User
+        || error.message().contains("timed out")
+}
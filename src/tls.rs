@@ -0,0 +1,132 @@
+use crate::result::Result;
+use clap::Args;
+use git2::cert::Cert;
+use git2::{CertificateCheckStatus, RemoteCallbacks};
+use log::{trace, warn};
+use openssl::nid::Nid;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::{X509StoreContext, X509};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Args, Serialize, Debug, Clone)]
+pub struct TlsArgs {
+    /// PEM file with additional CA certificates that are trusted for HTTPS
+    /// remotes. It has to contain intermediate certificates as well.
+    #[arg(long, env = "DEPLOYMENT_CA_BUNDLE")]
+    ca_bundle: Option<PathBuf>,
+
+    /// Do not verify TLS certificates at all. This is DANGEROUS and only
+    /// meant for lab environments
+    #[arg(
+        long,
+        conflicts_with = "ca_bundle",
+        env = "DEPLOYMENT_INSECURE_SKIP_TLS_VERIFY"
+    )]
+    insecure_skip_tls_verify: bool,
+}
+
+impl TlsArgs {
+    /// Installs a certificate check in `callbacks` if the default validation
+    /// of libgit2 is not sufficient
+    pub fn install(&self, callbacks: &mut RemoteCallbacks<'_>) -> Result<()> {
+        if self.insecure_skip_tls_verify {
+            warn!("TLS certificate verification is disabled");
+            callbacks.certificate_check(|_, _| {
+                Ok(CertificateCheckStatus::CertificateOk)
+            });
+        } else if let Some(ca_bundle) = &self.ca_bundle {
+            let mut builder = X509StoreBuilder::new()?;
+            builder.set_default_paths()?;
+            let pem = fs::read(ca_bundle).map_err(|e| {
+                format!("Could not read {}: {}", ca_bundle.display(), e)
+            })?;
+            for certificate in X509::stack_from_pem(&pem)? {
+                builder.add_cert(certificate)?;
+            }
+            let store = builder.build();
+            callbacks.certificate_check(move |cert, host| {
+                check_certificate(&store, cert, host)
+            });
+        }
+        Ok(())
+    }
+}
+
+fn check_certificate(
+    store: &X509Store,
+    cert: &Cert<'_>,
+    host: &str,
+) -> std::result::Result<CertificateCheckStatus, git2::Error> {
+    // SSH host keys are left to libgit2
+    let x509 = match cert.as_x509() {
+        Some(x509) => x509,
+        None => return Ok(CertificateCheckStatus::CertificatePassthrough),
+    };
+    verify(store, x509.data(), host).map_err(|e| git2::Error::from_str(&e))?;
+    trace!("Certificate of {} is valid", host);
+    Ok(CertificateCheckStatus::CertificateOk)
+}
+
+fn verify(
+    store: &X509Store,
+    der: &[u8],
+    host: &str,
+) -> std::result::Result<(), String> {
+    let certificate = X509::from_der(der)
+        .map_err(|e| format!("Invalid certificate from {}: {}", host, e))?;
+    let subject = certificate
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|name| name.to_string())
+        .unwrap_or_default();
+    let fail = |reason: &str| {
+        format!(
+            "Certificate of {} (subject CN={}) failed validation: {}",
+            host, subject, reason
+        )
+    };
+
+    let chain = Stack::new().map_err(|e| fail(&e.to_string()))?;
+    let mut context =
+        X509StoreContext::new().map_err(|e| fail(&e.to_string()))?;
+    let error = context
+        .init(store, &certificate, &chain, |context| {
+            context.verify_cert()?;
+            Ok(context.error())
+        })
+        .map_err(|e| fail(&e.to_string()))?;
+    if error.as_raw() != 0 {
+        return Err(fail(error.error_string()));
+    }
+
+    let names = match certificate.subject_alt_names() {
+        Some(names) => names
+            .iter()
+            .filter_map(|name| name.dnsname().map(|name| name.to_string()))
+            .collect(),
+        None => vec![subject.clone()],
+    };
+    if names.iter().any(|name| matches_host(name, host)) {
+        Ok(())
+    } else {
+        Err(fail(&format!("issued for {}", names.join(", "))))
+    }
+}
+
+/// Matches a certificate name against a host, supporting wildcards for the
+/// leftmost label
+fn matches_host(name: &str, host: &str) -> bool {
+    let name = name.to_lowercase();
+    let host = host.to_lowercase();
+    match name.strip_prefix("*.") {
+        Some(domain) => host
+            .split_once('.')
+            .is_some_and(|(_, host_domain)| host_domain == domain),
+        None => name == host,
+    }
+}
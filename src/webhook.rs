@@ -0,0 +1,53 @@
+use crate::result::{PipelineError, Result};
+use crate::subcommands::pipeline::PipelineOutcome;
+use log::trace;
+use serde::Serialize;
+use std::time::Duration;
+
+/// A slow webhook must not hold up the pipeline
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sent to `--webhook-url`. Incoming webhooks of Slack and Microsoft Teams
+/// show `text` and ignore the other fields.
+#[derive(Serialize)]
+struct Payload<'a> {
+    text: String,
+    composite_repository: &'a str,
+    submodule_path: Option<&'a str>,
+    previous_commit: Option<&'a str>,
+    new_commit: &'a str,
+    composite_commit: &'a str,
+    branch: &'a str,
+}
+
+/// Posts a summary of a pushed update to `url`. Webhook URLs often contain
+/// a secret, so errors do not mention it.
+pub fn notify(url: &str, outcome: &PipelineOutcome) -> Result<()> {
+    let submodule_path = outcome.submodule_path.as_deref();
+    let payload = Payload {
+        text: format!(
+            "Updated {} to {} on {} of {}",
+            submodule_path.unwrap_or("the submodule"),
+            &outcome.new_commit[..outcome.new_commit.len().min(10)],
+            outcome.target_branch,
+            outcome.composite_repository
+        ),
+        composite_repository: &outcome.composite_repository,
+        submodule_path,
+        previous_commit: outcome.previous_commit.as_deref(),
+        new_commit: &outcome.new_commit,
+        composite_commit: &outcome.composite_commit,
+        branch: &outcome.target_branch,
+    };
+    trace!("Notifying the webhook: {}", payload.text);
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    match agent.post(url).send_json(&payload) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) => Err(PipelineError::Other(
+            format!("The webhook responded with status {}", status),
+        )),
+        Err(ureq::Error::Transport(transport)) => Err(PipelineError::Other(
+            format!("Could not reach the webhook: {}", transport.kind()),
+        )),
+    }
+}